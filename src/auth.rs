@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use warp::{Filter, Rejection};
+use crate::error::ApiError;
+
+pub const SESSION_COOKIE: &str = "sr_session";
+const SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+#[derive(Clone)]
+pub struct HostAuth {
+    password: Arc<String>,
+    sessions: Arc<RwLock<HashMap<String, Instant>>>,
+}
+
+impl HostAuth {
+    pub fn new(password: String) -> Self {
+        Self {
+            password: Arc::new(password),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Checks the shared secret and starts a new session, returning its token.
+    pub fn login(&self, password: &str) -> Option<String> {
+        if !constant_time_eq(password.as_bytes(), self.password.as_bytes()) {
+            return None;
+        }
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, created| created.elapsed() < SESSION_TTL);
+        sessions.insert(token.clone(), Instant::now());
+        Some(token)
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.sessions.write().unwrap().remove(token);
+    }
+
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.sessions
+            .read()
+            .unwrap()
+            .get(token)
+            .is_some_and(|created| created.elapsed() < SESSION_TTL)
+    }
+
+    pub fn session_cookie(token: &str) -> String {
+        format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+            SESSION_COOKIE,
+            token,
+            SESSION_TTL.as_secs()
+        )
+    }
+
+    pub fn expired_cookie() -> String {
+        format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", SESSION_COOKIE)
+    }
+}
+
+/// Extracts the session token from a `Bearer` authorization header or the session cookie.
+pub fn session_token() -> impl Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .map(|authorization: Option<String>, cookie: Option<String>| {
+            authorization
+                .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string))
+                .or(cookie)
+        })
+        .or_else(|_| async { Ok::<_, std::convert::Infallible>((None,)) })
+}
+
+/// Rejects the request with `ApiError::Unauthorized` unless it carries a valid host session.
+pub fn with_host_auth(auth: HostAuth) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    session_token()
+        .and_then(move |token: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                match token {
+                    Some(token) if auth.is_valid(&token) => Ok(()),
+                    _ => Err(warp::reject::custom(ApiError::Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Resolves to whether the request carries a valid host session, without rejecting.
+pub fn is_host(auth: HostAuth) -> impl Filter<Extract = (bool,), Error = std::convert::Infallible> + Clone {
+    session_token().map(move |token: Option<String>| token.is_some_and(|token| auth.is_valid(&token)))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_equal_bytes_compare_equal() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret "));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn sessions_last_until_logout() {
+        let auth = HostAuth::new("hunter22".to_string());
+        assert!(auth.login("hunter2").is_none());
+
+        let token = auth.login("hunter22").unwrap();
+        assert!(auth.is_valid(&token));
+        assert!(!auth.is_valid("not-a-token"));
+
+        auth.logout(&token);
+        assert!(!auth.is_valid(&token));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::database::Database;
+use crate::models::*;
+use crate::youtube::YouTubeAPI;
+use anyhow::Result;
+
+/// A snapshot of the blocklist, loaded once per request or recommendation.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    entries: Vec<BlocklistEntry>,
+}
+
+/// The blocklist entry that matched a request.
+#[derive(Debug)]
+pub struct Blocked(BlocklistEntry);
+
+impl Blocklist {
+    pub async fn load(db: &Database) -> Result<Self> {
+        Ok(Self {
+            entries: db.get_blocklist().await?,
+        })
+    }
+
+    /// Turns user input into the stored form: video URLs become ids, keywords and names are lowercased.
+    pub fn normalize(kind: BlockKind, value: &str) -> Option<String> {
+        let value = value.trim();
+        let value = match kind {
+            BlockKind::Video => YouTubeAPI::extract_video_id(value).unwrap_or_else(|| value.to_string()),
+            BlockKind::Channel => value.to_string(),
+            BlockKind::Keyword | BlockKind::User => value.to_lowercase(),
+        };
+        (!value.is_empty()).then_some(value)
+    }
+
+    pub fn check_user(&self, user: &str) -> Result<(), Blocked> {
+        let user = user.trim().to_lowercase();
+        self.find(|entry| entry.kind == BlockKind::User && entry.value == user)
+    }
+
+    pub fn check_song(&self, video_id: &str, channel_id: Option<&str>, title: &str) -> Result<(), Blocked> {
+        let title = title.to_lowercase();
+        self.find(|entry| match entry.kind {
+            BlockKind::Video => entry.value == video_id,
+            BlockKind::Channel => channel_id == Some(entry.value.as_str()),
+            BlockKind::Keyword => title.contains(&entry.value),
+            BlockKind::User => false,
+        })
+    }
+
+    pub fn allows(&self, song: &YouTubeSearchResult) -> bool {
+        self.check_song(&song.video_id, song.channel_id.as_deref(), &song.title).is_ok()
+    }
+
+    fn find(&self, matches: impl Fn(&BlocklistEntry) -> bool) -> Result<(), Blocked> {
+        match self.entries.iter().find(|entry| matches(entry)) {
+            Some(entry) => Err(Blocked(entry.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Blocked {
+    pub fn message(&self) -> String {
+        let message = match self.0.kind {
+            BlockKind::Video => "That song is blocked".to_string(),
+            BlockKind::Channel => "Songs from that channel are blocked".to_string(),
+            BlockKind::Keyword => format!("Songs matching \"{}\" are blocked", self.0.value),
+            BlockKind::User => "You are not allowed to request songs".to_string(),
+        };
+        match &self.0.reason {
+            Some(reason) if !reason.trim().is_empty() => format!("{}: {}", message, reason.trim()),
+            _ => message,
+        }
+    }
+}
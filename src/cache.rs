@@ -0,0 +1,90 @@
+use std::time::Duration;
+use crate::database::Database;
+use crate::models::CacheKind;
+
+/// Remembers API responses in the `search_cache` table so repeated lookups do not spend quota.
+/// Cache errors are logged and treated as misses; they never fail a request.
+#[derive(Clone)]
+pub struct SearchCache {
+    db: Database,
+    // None disables caching
+    ttl: Option<Duration>,
+}
+
+impl SearchCache {
+    pub fn new(db: Database, ttl: Option<Duration>) -> Self {
+        Self { db, ttl }
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Queries differing only in case or spacing share a cache entry.
+    pub fn search_key(query: &str) -> String {
+        query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+
+    pub fn playlist_page_key(playlist_id: &str, page_token: Option<&str>) -> String {
+        format!("{}:{}", playlist_id, page_token.unwrap_or_default())
+    }
+
+    pub async fn get(&self, kind: CacheKind, key: &str) -> Option<String> {
+        let ttl = self.ttl?;
+        match self.db.get_cached_response(kind, key, ttl.as_secs()).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Error reading search cache");
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, kind: CacheKind, key: &str, response: &str) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        if let Err(e) = self.db.delete_expired_cache(ttl.as_secs()).await {
+            tracing::warn!(error = ?e, "Error expiring search cache");
+        }
+        if let Err(e) = self.db.store_cached_response(kind, key, response).await {
+            tracing::warn!(error = ?e, "Error writing search cache");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::TempDatabase;
+    use super::*;
+
+    #[tokio::test]
+    async fn responses_are_reused_until_they_expire() {
+        let file = TempDatabase::new();
+        let db = Database::new(&file.0).await.unwrap();
+        let cache = SearchCache::new(db.clone(), Some(Duration::from_secs(3600)));
+
+        let key = SearchCache::search_key("  Night   DRIVE ");
+        assert_eq!(key, "night drive");
+        assert_eq!(cache.get(CacheKind::Search, &key).await, None);
+        cache.put(CacheKind::Search, &key, "[1]").await;
+        assert_eq!(cache.get(CacheKind::Search, &SearchCache::search_key("night drive")).await.as_deref(), Some("[1]"));
+        assert_eq!(cache.get(CacheKind::PlaylistPage, &key).await, None);
+
+        // Two hours on, the entry is past its TTL and removed by the next write
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", file.0.display())).await.unwrap();
+        sqlx::query("UPDATE search_cache SET created_at = datetime('now', '-2 hours')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(cache.get(CacheKind::Search, &key).await, None);
+        cache.put(CacheKind::PlaylistPage, &SearchCache::playlist_page_key("PLfixture", None), "{}").await;
+        let keys: Vec<String> = db.get_search_cache().await.unwrap().into_iter().map(|entry| entry.key).collect();
+        assert_eq!(keys, ["PLfixture:"]);
+
+        let disabled = SearchCache::new(db, None);
+        disabled.put(CacheKind::Search, &key, "[2]").await;
+        assert_eq!(disabled.get(CacheKind::Search, &key).await, None);
+        assert_eq!(disabled.get(CacheKind::PlaylistPage, "PLfixture:").await, None);
+    }
+}
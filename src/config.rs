@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use crate::limits::{RequestPolicy, SongLength};
+use crate::logging::{self, LogFormat, LoggingConfig};
+use crate::quota::QuotaPolicy;
+use crate::database;
+use crate::recommend::{RecommendationSettings, RoomSettings};
+use crate::skip::SkipThreshold;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_MIN_SONG_SECONDS: i64 = 61;
+const DEFAULT_SEARCH_CACHE_HOURS: i64 = 24;
+const DEFAULT_SEARCHES_PER_MINUTE: i64 = 10;
+// YouTube Data API default allocation
+const DEFAULT_DAILY_QUOTA: i64 = 10_000;
+const DEFAULT_QUOTA_RESERVE: i64 = 1_000;
+
+/// Command line flags. Every flag can also be set through the environment (or `.env`).
+#[derive(Debug, Parser)]
+#[command(version, about = "YouTube song request server")]
+struct Cli {
+    /// Path to a TOML config file [default: ./config.toml if it exists]
+    #[arg(long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long, env = "BIND_ADDRESS")]
+    bind_address: Option<IpAddr>,
+
+    /// Port to listen on [default: 8080]
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+
+    /// SQLite database file, created if absent [default: ./youtube_urls.db]
+    #[arg(long, env = "DATABASE_PATH")]
+    database_path: Option<PathBuf>,
+
+    /// Where songs come from; `fixture` answers from a local song list without network access [default: youtube]
+    #[arg(long, env = "MUSIC_PROVIDER", value_enum)]
+    provider: Option<ProviderKind>,
+
+    /// JSON song list for the fixture provider [default: built-in fixtures]
+    #[arg(long, env = "FIXTURE_FILE")]
+    fixture_file: Option<PathBuf>,
+
+    /// YouTube Data API v3 key
+    #[arg(long, env = "YOUTUBE_API_KEY", hide_env_values = true)]
+    youtube_api_key: Option<String>,
+
+    /// Directory holding requester.html, host.html and other static files [default: ./static]
+    #[arg(long, env = "STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+
+    /// Allowed CORS origin, repeatable or comma separated; `*` allows any [default: *]
+    #[arg(long = "cors-origin", env = "CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Vec<String>,
+
+    /// Shared secret for host-only routes
+    #[arg(long, env = "HOST_PASSWORD", hide_env_values = true)]
+    host_password: Option<String>,
+
+    /// What to log, as a level like `debug` or per module like `rust_sr=debug,warp=warn` [default: info]
+    #[arg(long, env = "LOG_LEVEL")]
+    log_level: Option<String>,
+
+    /// Log output; `json` writes one object per line [default: text]
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    log_format: Option<LogFormat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<IpAddr>,
+    port: Option<u16>,
+    database_path: Option<PathBuf>,
+    provider: Option<ProviderKind>,
+    fixture_file: Option<PathBuf>,
+    youtube_api_key: Option<String>,
+    static_dir: Option<PathBuf>,
+    cors_origins: Option<Vec<String>>,
+    host_password: Option<String>,
+    log_level: Option<String>,
+    log_format: Option<LogFormat>,
+    skip_vote_threshold: Option<String>,
+    search_cache_hours: Option<i64>,
+    limits: LimitsSection,
+    quota: QuotaSection,
+    recommendations: RecommendationSettings,
+    rooms: HashMap<String, RoomSection>,
+    twitch: TwitchSection,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum ProviderKind {
+    Youtube,
+    Fixture,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+    max_pending_per_user: Option<i64>,
+    request_cooldown_secs: Option<i64>,
+    max_queue_length: Option<i64>,
+    min_song_seconds: Option<i64>,
+    max_song_seconds: Option<i64>,
+    searches_per_minute: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct QuotaSection {
+    daily_limit: Option<i64>,
+    reserve: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RoomSection {
+    // Only the keys to change from [recommendations]
+    recommendations: toml::Table,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[cfg_attr(not(feature = "twitch"), allow(dead_code))]
+struct TwitchSection {
+    channel: Option<String>,
+    nick: Option<String>,
+    token: Option<String>,
+    irc_host: Option<String>,
+    irc_port: Option<u16>,
+    irc_tls: Option<bool>,
+    room: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProviderConfig {
+    YouTube { api_key: String },
+    // None uses the fixtures built into the binary
+    Fixture { path: Option<PathBuf> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub database_path: PathBuf,
+    pub provider: ProviderConfig,
+    pub static_dir: PathBuf,
+    // Empty means any origin is allowed
+    pub cors_origins: Vec<String>,
+    pub host_password: Option<String>,
+    pub logging: LoggingConfig,
+    pub request_policy: RequestPolicy,
+    pub skip_threshold: SkipThreshold,
+    // None disables the search cache
+    pub search_cache_ttl: Option<Duration>,
+    pub quota: QuotaPolicy,
+    pub recommendations: RoomSettings,
+    // Re-read when recommendation settings are reloaded
+    pub config_file: Option<PathBuf>,
+    #[cfg(feature = "twitch")]
+    pub twitch: Option<crate::twitch::TwitchConfig>,
+}
+
+impl Config {
+    /// Builds the config from, in increasing priority: defaults, the TOML file, environment variables and CLI flags.
+    pub fn load() -> Result<Self> {
+        Self::from_cli(Cli::parse(), &Env::Process)
+    }
+
+    /// Like `load`, with `args` as the command line and `env` as the whole environment.
+    #[cfg(test)]
+    pub fn from_args(args: &[&str], env: &[(&str, &str)]) -> Result<Self> {
+        use clap::{CommandFactory, FromArgMatches};
+
+        let env: HashMap<String, String> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        // clap reads flags' variables itself; hand it the ones from `env` as defaults instead
+        let command = Cli::command().mut_args(|arg| {
+            let value = arg.get_env().and_then(|name| env.get(name.to_str()?)).cloned();
+            let arg = arg.env(None::<&str>);
+            match value {
+                Some(value) => arg.default_value(&*Box::leak(value.into_boxed_str())),
+                None => arg,
+            }
+        });
+        let matches = command.try_get_matches_from(std::iter::once("rust-sr").chain(args.iter().copied()))?;
+        Self::from_cli(Cli::from_arg_matches(&matches)?, &Env::Fixed(env))
+    }
+
+    fn from_cli(cli: Cli, env: &Env) -> Result<Self> {
+        let file = load_file(cli.config.as_deref())?;
+        let config_file = cli.config.clone();
+
+        let provider = match cli.provider.or(file.provider).unwrap_or(ProviderKind::Youtube) {
+            ProviderKind::Youtube => ProviderConfig::YouTube {
+                api_key: cli
+                    .youtube_api_key
+                    .or(file.youtube_api_key)
+                    .filter(|key| !key.trim().is_empty())
+                    .context(
+                        "YouTube API key is not configured. Set YOUTUBE_API_KEY in .env, \
+                         youtube_api_key in config.toml, or pass --youtube-api-key",
+                    )?,
+            },
+            ProviderKind::Fixture => ProviderConfig::Fixture {
+                path: cli.fixture_file.or(file.fixture_file),
+            },
+        };
+
+        let cors_origins = if cli.cors_origins.is_empty() {
+            file.cors_origins.unwrap_or_default()
+        } else {
+            cli.cors_origins
+        };
+        let cors_origins = parse_cors_origins(cors_origins)?;
+
+        let logging = LoggingConfig {
+            filter: cli.log_level.or(file.log_level).unwrap_or_else(|| "info".to_string()),
+            format: cli.log_format.or(file.log_format).unwrap_or(LogFormat::Text),
+        };
+        logging::validate_filter(&logging.filter)
+            .map_err(|e| anyhow::anyhow!("Invalid log level '{}': {}", logging.filter, e))?;
+
+        let limits = file.limits;
+        let request_policy = RequestPolicy {
+            max_pending_per_user: positive(env.var("MAX_PENDING_PER_USER")?.or(limits.max_pending_per_user)),
+            min_interval: positive(env.var("REQUEST_COOLDOWN_SECS")?.or(limits.request_cooldown_secs))
+                .map(|secs| Duration::from_secs(secs as u64)),
+            max_queue_length: positive(env.var("MAX_QUEUE_LENGTH")?.or(limits.max_queue_length)),
+            song_length: SongLength {
+                // Keeps YouTube Shorts out unless explicitly set to 0
+                min_seconds: positive(
+                    env.var("MIN_SONG_SECONDS")?
+                        .or(limits.min_song_seconds)
+                        .or(Some(DEFAULT_MIN_SONG_SECONDS)),
+                ),
+                max_seconds: positive(env.var("MAX_SONG_SECONDS")?.or(limits.max_song_seconds)),
+            },
+            searches_per_minute: positive(
+                env.var("SEARCHES_PER_MINUTE")?
+                    .or(limits.searches_per_minute)
+                    .or(Some(DEFAULT_SEARCHES_PER_MINUTE)),
+            ),
+        };
+
+        let skip_threshold = match env.var::<String>("SKIP_VOTE_THRESHOLD")?.or(file.skip_vote_threshold) {
+            Some(value) => SkipThreshold::parse(&value).with_context(|| {
+                format!("Invalid skip vote threshold '{}', expected a number like 3 or a percentage like 40%", value)
+            })?,
+            None => SkipThreshold::Absolute(3),
+        };
+
+        let search_cache_ttl = positive(
+            env.var("SEARCH_CACHE_HOURS")?
+                .or(file.search_cache_hours)
+                .or(Some(DEFAULT_SEARCH_CACHE_HOURS)),
+        )
+        .map(|hours| Duration::from_secs(hours as u64 * 3600));
+
+        let quota = QuotaPolicy {
+            daily_limit: env.var("YOUTUBE_DAILY_QUOTA")?
+                .or(file.quota.daily_limit)
+                .unwrap_or(DEFAULT_DAILY_QUOTA)
+                .max(0),
+            reserve: env.var("YOUTUBE_QUOTA_RESERVE")?
+                .or(file.quota.reserve)
+                .unwrap_or(DEFAULT_QUOTA_RESERVE)
+                .max(0),
+        };
+
+        Ok(Self {
+            bind_address: cli
+                .bind_address
+                .or(file.bind_address)
+                .unwrap_or(IpAddr::from([127, 0, 0, 1])),
+            port: cli.port.or(file.port).unwrap_or(8080),
+            database_path: cli
+                .database_path
+                .or(file.database_path)
+                .unwrap_or_else(|| PathBuf::from("./youtube_urls.db")),
+            provider,
+            static_dir: cli
+                .static_dir
+                .or(file.static_dir)
+                .unwrap_or_else(|| PathBuf::from("static")),
+            cors_origins,
+            host_password: cli.host_password.or(file.host_password),
+            logging,
+            request_policy,
+            skip_threshold,
+            search_cache_ttl,
+            quota,
+            recommendations: room_settings(file.recommendations, file.rooms)?,
+            config_file,
+            #[cfg(feature = "twitch")]
+            twitch: twitch_config(file.twitch, env)?,
+        })
+    }
+
+    /// Values that must never show up in the logs.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets: Vec<String> = self.host_password.iter().cloned().collect();
+        if let ProviderConfig::YouTube { api_key } = &self.provider {
+            secrets.push(api_key.clone());
+        }
+        #[cfg(feature = "twitch")]
+        if let Some(token) = self.twitch.as_ref().and_then(|twitch| twitch.token.clone()) {
+            secrets.push(token);
+        }
+        secrets
+    }
+}
+
+/// Re-reads the `[recommendations]` and `[rooms]` sections so filters can be changed without a restart.
+pub fn reload_recommendations(config_file: Option<&Path>) -> Result<RoomSettings> {
+    let file = load_file(config_file)?;
+    room_settings(file.recommendations, file.rooms)
+}
+
+fn room_settings(default: RecommendationSettings, rooms: HashMap<String, RoomSection>) -> Result<RoomSettings> {
+    let base = toml::Value::try_from(&default)?;
+    let mut settings = RoomSettings {
+        default: default.normalized(),
+        rooms: HashMap::new(),
+    };
+
+    for (room, section) in rooms {
+        let name = database::room_name(&room)
+            .with_context(|| format!("Invalid room name '{}', use letters, digits, - and _", room))?;
+        let mut merged = base.as_table().cloned().unwrap_or_default();
+        merged.extend(section.recommendations);
+        let room_settings: RecommendationSettings = toml::Value::Table(merged)
+            .try_into()
+            .with_context(|| format!("Invalid [rooms.{}.recommendations]", room))?;
+        settings.rooms.insert(name, room_settings.normalized());
+    }
+    Ok(settings)
+}
+
+fn load_file(path: Option<&Path>) -> Result<FileConfig> {
+    let path = match path {
+        Some(path) => path,
+        // The default file is optional, an explicitly requested one is not
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+        None => return Ok(FileConfig::default()),
+    };
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read config file {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+}
+
+/// Where settings without a flag of their own are read from.
+enum Env {
+    Process,
+    // Only these variables, so tests do not depend on the shell they run in
+    #[cfg(test)]
+    Fixed(HashMap<String, String>),
+}
+
+impl Env {
+    /// Reads an optional variable, failing with a readable message if it does not parse.
+    fn var<T: FromStr>(&self, name: &str) -> Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let value = match self {
+            Env::Process => std::env::var(name).ok(),
+            #[cfg(test)]
+            Env::Fixed(variables) => variables.get(name).cloned(),
+        };
+        match value {
+            Some(value) if !value.trim().is_empty() => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Invalid value for {}: {}", name, e)),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn positive(value: Option<i64>) -> Option<i64> {
+    value.filter(|value| *value > 0)
+}
+
+fn parse_cors_origins(origins: Vec<String>) -> Result<Vec<String>> {
+    let origins: Vec<String> = origins
+        .into_iter()
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(Vec::new());
+    }
+
+    for origin in &origins {
+        let valid = origin
+            .split_once("://")
+            .filter(|(scheme, _)| *scheme == "http" || *scheme == "https")
+            .is_some_and(|(_, authority)| authority.parse::<warp::http::uri::Authority>().is_ok());
+        if !valid {
+            bail!("Invalid CORS origin '{}', expected something like https://example.com", origin);
+        }
+    }
+    Ok(origins)
+}
+
+#[cfg(feature = "twitch")]
+fn twitch_config(section: TwitchSection, env: &Env) -> Result<Option<crate::twitch::TwitchConfig>> {
+    let channel = env.var::<String>("TWITCH_CHANNEL")?
+        .or(section.channel)
+        .map(|channel| channel.trim().trim_start_matches('#').to_lowercase())
+        .filter(|channel| !channel.is_empty());
+    let Some(channel) = channel else {
+        return Ok(None);
+    };
+    let room = match env.var::<String>("TWITCH_ROOM")?.or(section.room) {
+        Some(room) => database::room_name(room.trim())
+            .with_context(|| format!("Invalid Twitch room '{}', use letters, digits, - and _", room))?,
+        None => database::DEFAULT_ROOM.to_string(),
+    };
+
+    let nick = env.var::<String>("TWITCH_BOT_NICK")?.or(section.nick);
+    let token = env.var::<String>("TWITCH_BOT_TOKEN")?.or(section.token);
+    if token.is_some() && nick.is_none() {
+        bail!("TWITCH_BOT_TOKEN is set without TWITCH_BOT_NICK, set the account the token belongs to");
+    }
+    let tls = env.var("TWITCH_IRC_TLS")?.or(section.irc_tls).unwrap_or(true);
+
+    Ok(Some(crate::twitch::TwitchConfig {
+        host: env.var("TWITCH_IRC_HOST")?
+            .or(section.irc_host)
+            .unwrap_or_else(|| "irc.chat.twitch.tv".to_string()),
+        port: env.var("TWITCH_IRC_PORT")?
+            .or(section.irc_port)
+            .unwrap_or(if tls { 6697 } else { 6667 }),
+        tls,
+        // Twitch accepts anonymous read-only logins as justinfanNNNN
+        nick: nick.unwrap_or_else(|| "justinfan12345".to_string()),
+        token,
+        channel,
+        room,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rust-sr-test-{}.toml", uuid::Uuid::new_v4().simple()));
+            std::fs::write(&path, contents).unwrap();
+            TempConfig(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn defaults_apply_without_settings() {
+        let config = Config::from_args(&["--config", "/dev/null", "--provider", "fixture"], &[]).unwrap();
+        assert_eq!(config.bind_address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.request_policy.song_length.min_seconds, Some(DEFAULT_MIN_SONG_SECONDS));
+        assert_eq!(config.request_policy.max_pending_per_user, None);
+    }
+
+    #[test]
+    fn flags_override_the_environment_and_the_environment_overrides_the_file() {
+        let file = TempConfig::new(
+            "bind_address = \"0.0.0.0\"\nport = 9000\nprovider = \"fixture\"\n\n\
+             [limits]\nmax_pending_per_user = 2\nrequest_cooldown_secs = 30\n",
+        );
+        let path = file.0.to_str().unwrap();
+
+        let config = Config::from_args(
+            &["--config", path, "--port", "9002"],
+            &[("PORT", "9001"), ("MAX_PENDING_PER_USER", "4")],
+        )
+        .unwrap();
+        assert_eq!(config.port, 9002);
+        assert_eq!(config.request_policy.max_pending_per_user, Some(4));
+        // Settings only the file sets still come from it
+        assert_eq!(config.bind_address, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.request_policy.min_interval, Some(Duration::from_secs(30)));
+
+        let config = Config::from_args(&["--config", path], &[("PORT", "9001")]).unwrap();
+        assert_eq!(config.port, 9001);
+        let config = Config::from_args(&["--config", path], &[]).unwrap();
+        assert_eq!(config.port, 9000);
+    }
+
+    #[test]
+    fn unparsable_values_name_the_setting() {
+        let error = Config::from_args(&["--config", "/dev/null", "--provider", "fixture"], &[("MAX_QUEUE_LENGTH", "lots")])
+            .unwrap_err();
+        assert!(error.to_string().contains("MAX_QUEUE_LENGTH"), "{}", error);
+    }
+}
@@ -1,152 +1,261 @@
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::{SqlitePool, Row};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::events::{EventBus, QueueEvent};
+use crate::migrations;
 use crate::models::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
 
+/// Room used by the routes without a `/rooms/{room}` prefix and by the chat bot.
+pub const DEFAULT_ROOM: &str = "default";
+
+/// Normalizes a room name from a URL or the config: up to 64 letters, digits, `-` and `_`, lowercased.
+pub fn room_name(name: &str) -> Option<String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| name.to_ascii_lowercase())
+}
+
+/// Whether `error` is a unique constraint failing, like queueing a song twice.
+pub fn is_unique_violation(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<sqlx::Error>()
+        .and_then(sqlx::Error::as_database_error)
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+// `AND` conditions for the `user` and `q` filters of a list query, with the values to bind in order
+fn list_conditions(query: &ListQuery) -> (String, Vec<String>) {
+    let mut conditions = String::new();
+    let mut binds = Vec::new();
+
+    if let Some(user) = query.user.as_deref().map(str::trim).filter(|user| !user.is_empty()) {
+        conditions.push_str(" AND LOWER(TRIM(user)) = LOWER(?)");
+        binds.push(user.to_string());
+    }
+    if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        // LIKE ignores ASCII case; wildcards typed by the user are matched literally
+        let escaped = q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        conditions.push_str(" AND title LIKE ? ESCAPE '\\'");
+        binds.push(format!("%{}%", escaped));
+    }
+
+    (conditions, binds)
+}
+
+// `ORDER BY` columns of a list query; `queue_order` is the natural order of the table
+fn list_order(query: &ListQuery, queue_order: &[&str]) -> String {
+    let columns: &[&str] = match query.sort.unwrap_or_default() {
+        ListSort::Queue => queue_order,
+        ListSort::Added => &["created_at", "id"],
+        ListSort::Title => &["title COLLATE NOCASE", "id"],
+        ListSort::User => &["user COLLATE NOCASE", "id"],
+    };
+    let order = query.order.unwrap_or_default().as_sql();
+    columns
+        .iter()
+        .map(|column| format!("{} {}", column, order))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A handle on the database scoped to one room. Queues, play history, playlist progress and
+/// recommendation history belong to the room; saved playlists, the blocklist, the search cache
+/// and quota usage are shared by all rooms.
 #[derive(Clone)]
 pub struct Database {
     pool: SqlitePool,
+    room: String,
+    events: EventBus,
+    // One event bus per room in use, created on first use
+    buses: Arc<Mutex<HashMap<String, EventBus>>>,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = SqlitePool::connect(database_url).await?;
-        let db = Database { pool };
-        db.init_tables().await?;
-        Ok(db)
+    pub async fn new(database_path: &Path) -> Result<Self> {
+        if let Some(parent) = database_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let options = SqliteConnectOptions::new()
+            .filename(database_path)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        migrations::run(&pool).await?;
+
+        let events = EventBus::new(256);
+        Ok(Database {
+            pool,
+            room: DEFAULT_ROOM.to_string(),
+            events: events.clone(),
+            buses: Arc::new(Mutex::new(HashMap::from([(DEFAULT_ROOM.to_string(), events)]))),
+        })
     }
 
-    async fn init_tables(&self) -> Result<()> {
-        // Create youtube_urls table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS youtube_urls (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                url TEXT NOT NULL UNIQUE,
-                user TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    /// The same database scoped to another room.
+    pub fn room(&self, room: &str) -> Database {
+        let mut buses = self.buses.lock().unwrap();
+        // Any room name gets a bus, so drop the ones nobody holds. Events are not stored, so a
+        // room that is used again simply starts on a new bus.
+        buses.retain(|_, bus| !bus.is_unused());
+        let events = buses.entry(room.to_string()).or_insert_with(|| EventBus::new(256)).clone();
+        drop(buses);
+        Database {
+            pool: self.pool.clone(),
+            room: room.to_string(),
+            events,
+            buses: self.buses.clone(),
+        }
+    }
 
-        // Create playlists table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS playlists (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                description TEXT,
-                youtube_playlist_url TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    pub fn room_name(&self) -> &str {
+        &self.room
+    }
 
-        // Create playlist_items table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS playlist_items (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                playlist_id INTEGER NOT NULL,
-                title TEXT NOT NULL,
-                url TEXT NOT NULL,
-                user TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
 
-        // Create recommended_videos table
-        sqlx::query(
+    /// Rooms with anything queued or played, with their queue lengths.
+    pub async fn get_rooms(&self) -> Result<Vec<RoomSummary>> {
+        let rows = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS recommended_videos (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                video_id TEXT NOT NULL UNIQUE,
-                timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
+            SELECT room,
+                (SELECT COUNT(*) FROM youtube_urls WHERE youtube_urls.room = rooms.room) AS queue_length,
+                (SELECT COUNT(*) FROM playlist_queue WHERE playlist_queue.room = rooms.room) AS playlist_queue_length,
+                (SELECT MAX(started_at) FROM play_history WHERE play_history.room = rooms.room) AS last_played_at
+            FROM (
+                SELECT room FROM youtube_urls
+                UNION SELECT room FROM playlist_queue
+                UNION SELECT room FROM play_history
+            ) AS rooms
+            ORDER BY room ASC
+            "#
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
 
-        // Create indexes for better performance
-        // Create playlist_queue table for separate playlist songs
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS playlist_queue (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                url TEXT NOT NULL,
-                video_id TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+        let rooms = rows
+            .into_iter()
+            .map(|row| RoomSummary {
+                room: row.get("room"),
+                queue_length: row.get("queue_length"),
+                playlist_queue_length: row.get("playlist_queue_length"),
+                last_played_at: row.get("last_played_at"),
+            })
+            .collect();
 
-        // Create playlist_progress table
-        sqlx::query(
+        Ok(rooms)
+    }
+
+    // YouTube URLs operations
+    pub async fn add_song(&self, song: &YouTubeURL) -> Result<i64> {
+        self.add_song_within(song, None, None)
+            .await?
+            .context("Song was not queued")
+    }
+
+    /// Queues a song unless its user already has `max_pending` songs waiting or the queue holds
+    /// `max_queue` songs. Both are counted in the insert itself, so concurrent requests cannot
+    /// all take the last free slot. Returns `None` when a limit stopped the song.
+    pub async fn add_song_within(&self, song: &YouTubeURL, max_pending: Option<i64>, max_queue: Option<i64>) -> Result<Option<i64>> {
+        let row = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS playlist_progress (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                playlist_id INTEGER NOT NULL,
-                video_id TEXT NOT NULL,
-                title TEXT NOT NULL,
-                url TEXT NOT NULL,
-                played_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE
-            )
+            INSERT INTO youtube_urls (room, title, url, user, duration_seconds, channel_id, position)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, (SELECT COALESCE(MAX(position), 0) + 1 FROM youtube_urls WHERE room = ?1)
+            WHERE (?7 IS NULL OR (SELECT COUNT(*) FROM youtube_urls WHERE room = ?1 AND LOWER(TRIM(user)) = LOWER(TRIM(?4))) < ?7)
+              AND (?8 IS NULL OR (SELECT COUNT(*) FROM youtube_urls WHERE room = ?1) < ?8)
+            RETURNING id, created_at
             "#,
         )
-        .execute(&self.pool)
+        .bind(&self.room)
+        .bind(&song.title)
+        .bind(&song.url)
+        .bind(&song.user)
+        .bind(song.duration_seconds)
+        .bind(&song.channel_id)
+        .bind(max_pending)
+        .bind(max_queue)
+        .fetch_optional(&self.pool)
         .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_video_id ON recommended_videos(video_id)")
-            .execute(&self.pool)
-            .await?;
+        let id: i64 = row.get("id");
+        self.events.publish(QueueEvent::SongAdded {
+            song: YouTubeURL {
+                id: Some(id),
+                created_at: row.get("created_at"),
+                ..song.clone()
+            },
+        });
+        Ok(Some(id))
+    }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_playlist_id ON playlist_items(playlist_id)")
-            .execute(&self.pool)
-            .await?;
+    /// Moves a song to `index` (0 is next to play), shifting the others. Returns false if the song is not queued.
+    pub async fn move_song(&self, id: i64, index: usize) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_playlist_progress_playlist_id ON playlist_progress(playlist_id)")
-            .execute(&self.pool)
-            .await?;
+        let mut order: Vec<i64> = sqlx::query("SELECT id FROM youtube_urls WHERE room = ? ORDER BY position ASC, id ASC")
+            .bind(&self.room)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|row| row.get("id"))
+            .collect();
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_playlist_progress_video_id ON playlist_progress(video_id)")
-            .execute(&self.pool)
-            .await?;
+        let Some(current) = order.iter().position(|song_id| *song_id == id) else {
+            return Ok(false);
+        };
+        order.remove(current);
+        order.insert(index.min(order.len()), id);
 
-        Ok(())
-    }
+        for (position, song_id) in order.iter().enumerate() {
+            sqlx::query("UPDATE youtube_urls SET position = ? WHERE id = ?")
+                .bind(position as i64 + 1)
+                .bind(song_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
 
-    // YouTube URLs operations
-    pub async fn add_song(&self, song: &YouTubeURL) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO youtube_urls (title, url, user) VALUES (?, ?, ?)"
-        )
-        .bind(&song.title)
-        .bind(&song.url)
-        .bind(&song.user)
-        .execute(&self.pool)
-        .await?;
-        Ok(())
+        self.events.publish(QueueEvent::QueueReordered { order });
+        Ok(true)
     }
 
     pub async fn get_all_songs(&self) -> Result<Vec<YouTubeURL>> {
-        let rows = sqlx::query(
-            "SELECT id, title, url, user, created_at FROM youtube_urls ORDER BY id ASC"
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        Ok(self.get_songs_page(&ListQuery::default()).await?.0)
+    }
+
+    /// A page of the queue, with the number of songs matching the filters.
+    pub async fn get_songs_page(&self, query: &ListQuery) -> Result<(Vec<YouTubeURL>, i64)> {
+        let (conditions, binds) = list_conditions(query);
+
+        let count_sql = format!("SELECT COUNT(*) AS count FROM youtube_urls WHERE room = ?{}", conditions);
+        let mut count = sqlx::query(&count_sql).bind(&self.room);
+        for value in &binds {
+            count = count.bind(value);
+        }
+        let total = count.fetch_one(&self.pool).await?.get("count");
+
+        let sql = format!(
+            "SELECT id, title, url, user, created_at, duration_seconds, channel_id FROM youtube_urls WHERE room = ?{} ORDER BY {} LIMIT ? OFFSET ?",
+            conditions,
+            list_order(query, &["position", "id"])
+        );
+        let mut page = sqlx::query(&sql).bind(&self.room);
+        for value in &binds {
+            page = page.bind(value);
+        }
+        let rows = page
+            .bind(query.limit.unwrap_or(-1))
+            .bind(query.offset.unwrap_or(0))
+            .fetch_all(&self.pool)
+            .await?;
 
         let songs = rows
             .into_iter()
@@ -156,16 +265,44 @@ impl Database {
                 url: row.get("url"),
                 user: row.get("user"),
                 created_at: row.get("created_at"),
+                duration_seconds: row.get("duration_seconds"),
+                channel_id: row.get("channel_id"),
             })
             .collect();
 
-        Ok(songs)
+        Ok((songs, total))
     }
 
     pub async fn get_oldest_song(&self) -> Result<Option<YouTubeURL>> {
         let row = sqlx::query(
-            "SELECT id, title, url, user, created_at FROM youtube_urls ORDER BY id ASC LIMIT 1"
+            "SELECT id, title, url, user, created_at, duration_seconds, channel_id FROM youtube_urls WHERE room = ? ORDER BY position ASC, id ASC LIMIT 1"
+        )
+        .bind(&self.room)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(row) = row {
+            Ok(Some(YouTubeURL {
+                id: Some(row.get("id")),
+                title: row.get("title"),
+                url: row.get("url"),
+                user: row.get("user"),
+                created_at: row.get("created_at"),
+                duration_seconds: row.get("duration_seconds"),
+                channel_id: row.get("channel_id"),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[cfg(feature = "twitch")]
+    pub async fn get_latest_song_by_user(&self, user: &str) -> Result<Option<YouTubeURL>> {
+        let row = sqlx::query(
+            "SELECT id, title, url, user, created_at, duration_seconds, channel_id FROM youtube_urls WHERE room = ? AND LOWER(TRIM(user)) = LOWER(TRIM(?)) ORDER BY id DESC LIMIT 1"
         )
+        .bind(&self.room)
+        .bind(user)
         .fetch_optional(&self.pool)
         .await?;
 
@@ -176,26 +313,60 @@ impl Database {
                 url: row.get("url"),
                 user: row.get("user"),
                 created_at: row.get("created_at"),
+                duration_seconds: row.get("duration_seconds"),
+                channel_id: row.get("channel_id"),
             }))
         } else {
             Ok(None)
         }
     }
 
+    pub async fn count_songs(&self) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM youtube_urls WHERE room = ?")
+            .bind(&self.room)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get("count"))
+    }
+
+    pub async fn count_songs_by_user(&self, user: &str) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM youtube_urls WHERE room = ? AND LOWER(TRIM(user)) = LOWER(TRIM(?))")
+            .bind(&self.room)
+            .bind(user)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get("count"))
+    }
+
     pub async fn delete_song_by_url(&self, url: &str) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM youtube_urls WHERE url = ?")
+        let row = sqlx::query("DELETE FROM youtube_urls WHERE room = ? AND url = ? RETURNING id, url")
+            .bind(&self.room)
             .bind(url)
-            .execute(&self.pool)
+            .fetch_optional(&self.pool)
             .await?;
-        Ok(result.rows_affected() > 0)
+        Ok(self.publish_song_removed(row))
     }
 
     pub async fn delete_song_by_id(&self, id: i64) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM youtube_urls WHERE id = ?")
+        let row = sqlx::query("DELETE FROM youtube_urls WHERE room = ? AND id = ? RETURNING id, url")
+            .bind(&self.room)
             .bind(id)
-            .execute(&self.pool)
+            .fetch_optional(&self.pool)
             .await?;
-        Ok(result.rows_affected() > 0)
+        Ok(self.publish_song_removed(row))
+    }
+
+    fn publish_song_removed(&self, row: Option<sqlx::sqlite::SqliteRow>) -> bool {
+        match row {
+            Some(row) => {
+                self.events.publish(QueueEvent::SongRemoved {
+                    id: row.get("id"),
+                    url: row.get("url"),
+                });
+                true
+            }
+            None => false,
+        }
     }
 
     // Playlist operations
@@ -266,13 +437,31 @@ impl Database {
         Ok(())
     }
 
-    pub async fn get_playlist_items(&self, playlist_id: i64) -> Result<Vec<PlaylistItem>> {
-        let rows = sqlx::query(
-            "SELECT id, playlist_id, title, url, user, created_at FROM playlist_items WHERE playlist_id = ? ORDER BY id ASC"
-        )
-        .bind(playlist_id)
-        .fetch_all(&self.pool)
-        .await?;
+    /// A page of the songs added to a playlist by hand, with the number matching the filters.
+    pub async fn get_playlist_items_page(&self, playlist_id: i64, query: &ListQuery) -> Result<(Vec<PlaylistItem>, i64)> {
+        let (conditions, binds) = list_conditions(query);
+
+        let count_sql = format!("SELECT COUNT(*) AS count FROM playlist_items WHERE playlist_id = ?{}", conditions);
+        let mut count = sqlx::query(&count_sql).bind(playlist_id);
+        for value in &binds {
+            count = count.bind(value);
+        }
+        let total = count.fetch_one(&self.pool).await?.get("count");
+
+        let sql = format!(
+            "SELECT id, playlist_id, title, url, user, created_at FROM playlist_items WHERE playlist_id = ?{} ORDER BY {} LIMIT ? OFFSET ?",
+            conditions,
+            list_order(query, &["id"])
+        );
+        let mut page = sqlx::query(&sql).bind(playlist_id);
+        for value in &binds {
+            page = page.bind(value);
+        }
+        let rows = page
+            .bind(query.limit.unwrap_or(-1))
+            .bind(query.offset.unwrap_or(0))
+            .fetch_all(&self.pool)
+            .await?;
 
         let items = rows
             .into_iter()
@@ -286,7 +475,7 @@ impl Database {
             })
             .collect();
 
-        Ok(items)
+        Ok((items, total))
     }
 
     pub async fn get_random_playlist_item(&self, playlist_id: i64) -> Result<Option<PlaylistItem>> {
@@ -314,16 +503,18 @@ impl Database {
     // Recommendation tracking
     pub async fn store_recommendation(&self, video_id: &str) -> Result<()> {
         sqlx::query(
-            "INSERT OR IGNORE INTO recommended_videos (video_id) VALUES (?)"
+            "INSERT OR IGNORE INTO recommended_videos (room, video_id) VALUES (?, ?)"
         )
+        .bind(&self.room)
         .bind(video_id)
         .execute(&self.pool)
         .await?;
 
-        // Clean up old recommendations (keep only last 200)
+        // Clean up old recommendations (keep only last 200 per room)
         sqlx::query(
-            "DELETE FROM recommended_videos WHERE id NOT IN (SELECT id FROM recommended_videos ORDER BY timestamp DESC LIMIT 200)"
+            "DELETE FROM recommended_videos WHERE room = ?1 AND id NOT IN (SELECT id FROM recommended_videos WHERE room = ?1 ORDER BY timestamp DESC LIMIT 200)"
         )
+        .bind(&self.room)
         .execute(&self.pool)
         .await?;
 
@@ -332,8 +523,9 @@ impl Database {
 
     pub async fn get_recent_recommendations(&self) -> Result<Vec<String>> {
         let rows = sqlx::query(
-            "SELECT video_id FROM recommended_videos WHERE timestamp > datetime('now', '-7 day') ORDER BY timestamp DESC LIMIT 100"
+            "SELECT video_id FROM recommended_videos WHERE room = ? AND timestamp > datetime('now', '-7 day') ORDER BY timestamp DESC LIMIT 100"
         )
+        .bind(&self.room)
         .fetch_all(&self.pool)
         .await?;
 
@@ -348,21 +540,51 @@ impl Database {
     // Playlist progress operations
     pub async fn mark_song_played(&self, playlist_id: i64, video_id: &str, title: &str, url: &str) -> Result<()> {
         sqlx::query(
-            "INSERT INTO playlist_progress (playlist_id, video_id, title, url) VALUES (?, ?, ?, ?)"
+            r#"
+            INSERT INTO playlist_progress (room, playlist_id, video_id, title, url)
+            SELECT ?5, ?1, ?2, ?3, ?4
+            WHERE NOT EXISTS (SELECT 1 FROM playlist_progress WHERE room = ?5 AND playlist_id = ?1 AND video_id = ?2)
+            "#
         )
         .bind(playlist_id)
         .bind(video_id)
         .bind(title)
         .bind(url)
+        .bind(&self.room)
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
+    pub async fn get_playlist_progress(&self, playlist_id: i64) -> Result<Vec<PlaylistProgress>> {
+        let rows = sqlx::query(
+            "SELECT id, playlist_id, video_id, title, url, played_at FROM playlist_progress WHERE room = ? AND playlist_id = ? ORDER BY played_at ASC, id ASC"
+        )
+        .bind(&self.room)
+        .bind(playlist_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let progress = rows
+            .into_iter()
+            .map(|row| PlaylistProgress {
+                id: Some(row.get("id")),
+                playlist_id: row.get("playlist_id"),
+                video_id: row.get("video_id"),
+                title: row.get("title"),
+                url: row.get("url"),
+                played_at: row.get("played_at"),
+            })
+            .collect();
+
+        Ok(progress)
+    }
+
     pub async fn get_played_songs(&self, playlist_id: i64) -> Result<Vec<String>> {
         let rows = sqlx::query(
-            "SELECT video_id FROM playlist_progress WHERE playlist_id = ?"
+            "SELECT video_id FROM playlist_progress WHERE room = ? AND playlist_id = ?"
         )
+        .bind(&self.room)
         .bind(playlist_id)
         .fetch_all(&self.pool)
         .await?;
@@ -377,8 +599,9 @@ impl Database {
 
     pub async fn reset_playlist_progress(&self, playlist_id: i64) -> Result<()> {
         sqlx::query(
-            "DELETE FROM playlist_progress WHERE playlist_id = ?"
+            "DELETE FROM playlist_progress WHERE room = ? AND playlist_id = ?"
         )
+        .bind(&self.room)
         .bind(playlist_id)
         .execute(&self.pool)
         .await?;
@@ -386,22 +609,38 @@ impl Database {
     }
 
     // Playlist queue operations
-    pub async fn add_to_playlist_queue(&self, title: &str, url: &str, video_id: &str) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO playlist_queue (title, url, video_id) VALUES (?, ?, ?)"
+    pub async fn add_to_playlist_queue(&self, song: &YouTubeSearchResult, playlist_id: Option<i64>) -> Result<()> {
+        let row = sqlx::query(
+            "INSERT INTO playlist_queue (room, title, url, video_id, duration_seconds, playlist_id) VALUES (?, ?, ?, ?, ?, ?) RETURNING id, created_at"
         )
-        .bind(title)
-        .bind(url)
-        .bind(video_id)
-        .execute(&self.pool)
+        .bind(&self.room)
+        .bind(&song.title)
+        .bind(&song.url)
+        .bind(&song.video_id)
+        .bind(song.duration_seconds)
+        .bind(playlist_id)
+        .fetch_one(&self.pool)
         .await?;
+
+        self.events.publish(QueueEvent::PlaylistSongAdded {
+            song: PlaylistQueueItem {
+                id: Some(row.get("id")),
+                title: song.title.clone(),
+                url: song.url.clone(),
+                video_id: song.video_id.clone(),
+                created_at: row.get("created_at"),
+                duration_seconds: song.duration_seconds,
+                playlist_id,
+            },
+        });
         Ok(())
     }
 
     pub async fn get_oldest_playlist_song(&self) -> Result<Option<PlaylistQueueItem>> {
         let row = sqlx::query(
-            "SELECT id, title, url, video_id, created_at FROM playlist_queue ORDER BY created_at ASC LIMIT 1"
+            "SELECT id, title, url, video_id, created_at, duration_seconds, playlist_id FROM playlist_queue WHERE room = ? ORDER BY created_at ASC LIMIT 1"
         )
+        .bind(&self.room)
         .fetch_optional(&self.pool)
         .await?;
 
@@ -412,6 +651,8 @@ impl Database {
                 url: row.get("url"),
                 video_id: row.get("video_id"),
                 created_at: row.get("created_at"),
+                duration_seconds: row.get("duration_seconds"),
+                playlist_id: row.get("playlist_id"),
             }))
         } else {
             Ok(None)
@@ -420,20 +661,50 @@ impl Database {
 
     pub async fn delete_playlist_song_by_id(&self, id: i64) -> Result<bool> {
         let result = sqlx::query(
-            "DELETE FROM playlist_queue WHERE id = ?"
+            "DELETE FROM playlist_queue WHERE room = ? AND id = ?"
         )
+        .bind(&self.room)
         .bind(id)
         .execute(&self.pool)
         .await?;
-        Ok(result.rows_affected() > 0)
+
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            self.events.publish(QueueEvent::PlaylistSongRemoved { id });
+        }
+        Ok(deleted)
     }
 
     pub async fn get_all_playlist_songs(&self) -> Result<Vec<PlaylistQueueItem>> {
-        let rows = sqlx::query(
-            "SELECT id, title, url, video_id, created_at FROM playlist_queue ORDER BY created_at ASC"
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        Ok(self.get_playlist_songs_page(&ListQuery::default()).await?.0)
+    }
+
+    /// A page of the playlist queue, with the number of songs matching the filters. Songs in
+    /// the playlist queue have no requester, so `user` must not be set.
+    pub async fn get_playlist_songs_page(&self, query: &ListQuery) -> Result<(Vec<PlaylistQueueItem>, i64)> {
+        let (conditions, binds) = list_conditions(query);
+
+        let count_sql = format!("SELECT COUNT(*) AS count FROM playlist_queue WHERE room = ?{}", conditions);
+        let mut count = sqlx::query(&count_sql).bind(&self.room);
+        for value in &binds {
+            count = count.bind(value);
+        }
+        let total = count.fetch_one(&self.pool).await?.get("count");
+
+        let sql = format!(
+            "SELECT id, title, url, video_id, created_at, duration_seconds, playlist_id FROM playlist_queue WHERE room = ?{} ORDER BY {} LIMIT ? OFFSET ?",
+            conditions,
+            list_order(query, &["created_at", "id"])
+        );
+        let mut page = sqlx::query(&sql).bind(&self.room);
+        for value in &binds {
+            page = page.bind(value);
+        }
+        let rows = page
+            .bind(query.limit.unwrap_or(-1))
+            .bind(query.offset.unwrap_or(0))
+            .fetch_all(&self.pool)
+            .await?;
 
         let songs = rows
             .into_iter()
@@ -443,16 +714,410 @@ impl Database {
                 url: row.get("url"),
                 video_id: row.get("video_id"),
                 created_at: row.get("created_at"),
+                duration_seconds: row.get("duration_seconds"),
+                playlist_id: row.get("playlist_id"),
             })
             .collect();
 
-        Ok(songs)
+        Ok((songs, total))
     }
 
     pub async fn clear_playlist_queue(&self) -> Result<()> {
-        sqlx::query("DELETE FROM playlist_queue")
+        sqlx::query("DELETE FROM playlist_queue WHERE room = ?")
+            .bind(&self.room)
+            .execute(&self.pool)
+            .await?;
+        self.events.publish(QueueEvent::PlaylistQueueCleared);
+        Ok(())
+    }
+
+    // Play history operations
+    pub async fn record_play(&self, song: &YouTubeURL, video_id: Option<&str>, source: PlaySource) -> Result<NowPlaying> {
+        let row = sqlx::query(
+            "INSERT INTO play_history (room, title, url, video_id, channel_id, source, requester, duration_seconds) VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id, started_at"
+        )
+        .bind(&self.room)
+        .bind(&song.title)
+        .bind(&song.url)
+        .bind(video_id)
+        .bind(&song.channel_id)
+        .bind(source.as_str())
+        .bind(&song.user)
+        .bind(song.duration_seconds)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let now_playing = NowPlaying {
+            id: Some(row.get("id")),
+            title: song.title.clone(),
+            url: song.url.clone(),
+            video_id: video_id.map(str::to_string),
+            channel_id: song.channel_id.clone(),
+            source,
+            requester: song.user.clone(),
+            started_at: row.get("started_at"),
+            duration_seconds: song.duration_seconds,
+        };
+
+        self.events.publish(QueueEvent::NowPlayingChanged {
+            now_playing: now_playing.clone(),
+        });
+        Ok(now_playing)
+    }
+
+    pub async fn get_now_playing(&self) -> Result<Option<NowPlaying>> {
+        let row = sqlx::query(
+            "SELECT id, title, url, video_id, channel_id, source, requester, started_at, duration_seconds FROM play_history WHERE room = ? ORDER BY id DESC LIMIT 1"
+        )
+        .bind(&self.room)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(Self::now_playing_from_row))
+    }
+
+    pub async fn get_play_history(&self, limit: i64, offset: i64) -> Result<Vec<NowPlaying>> {
+        let rows = sqlx::query(
+            "SELECT id, title, url, video_id, channel_id, source, requester, started_at, duration_seconds FROM play_history WHERE room = ? ORDER BY id DESC LIMIT ? OFFSET ?"
+        )
+        .bind(&self.room)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Self::now_playing_from_row).collect())
+    }
+
+    // Recommendation seeds, drawn from songs requested through the main queue
+    pub async fn get_requested_titles(&self, limit: i64) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT title FROM play_history WHERE room = ? AND source = 'main_queue' ORDER BY id DESC LIMIT ?"
+        )
+        .bind(&self.room)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("title")).collect())
+    }
+
+    pub async fn get_most_requested_titles(&self, days: i64, limit: i64) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT MAX(title) AS title FROM play_history WHERE room = ? AND source = 'main_queue' AND started_at > datetime('now', ?) GROUP BY url ORDER BY COUNT(*) DESC, MAX(id) DESC LIMIT ?"
+        )
+        .bind(&self.room)
+        .bind(format!("-{} day", days))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("title")).collect())
+    }
+
+    pub async fn get_top_requested_channels(&self, days: i64, limit: i64) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT channel_id FROM play_history WHERE room = ? AND source = 'main_queue' AND channel_id IS NOT NULL AND started_at > datetime('now', ?) GROUP BY channel_id ORDER BY COUNT(*) DESC, MAX(id) DESC LIMIT ?"
+        )
+        .bind(&self.room)
+        .bind(format!("-{} day", days))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("channel_id")).collect())
+    }
+
+    pub async fn get_recently_played_video_ids(&self, limit: i64) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT video_id FROM play_history WHERE room = ? AND video_id IS NOT NULL ORDER BY id DESC LIMIT ?"
+        )
+        .bind(&self.room)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("video_id")).collect())
+    }
+
+    fn now_playing_from_row(row: sqlx::sqlite::SqliteRow) -> NowPlaying {
+        let source: String = row.get("source");
+        NowPlaying {
+            id: Some(row.get("id")),
+            title: row.get("title"),
+            url: row.get("url"),
+            video_id: row.get("video_id"),
+            channel_id: row.get("channel_id"),
+            source: PlaySource::from_db(&source),
+            requester: row.get("requester"),
+            started_at: row.get("started_at"),
+            duration_seconds: row.get("duration_seconds"),
+        }
+    }
+
+    // Blocklist operations
+    pub async fn add_block(&self, kind: BlockKind, value: &str, reason: Option<&str>) -> Result<BlocklistEntry> {
+        let row = sqlx::query(
+            "INSERT INTO blocklist (kind, value, reason) VALUES (?, ?, ?) RETURNING id, created_at"
+        )
+        .bind(kind.as_str())
+        .bind(value)
+        .bind(reason)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(BlocklistEntry {
+            id: Some(row.get("id")),
+            kind,
+            value: value.to_string(),
+            reason: reason.map(str::to_string),
+            created_at: row.get("created_at"),
+        })
+    }
+
+    pub async fn get_blocklist(&self) -> Result<Vec<BlocklistEntry>> {
+        let rows = sqlx::query(
+            "SELECT id, kind, value, reason, created_at FROM blocklist ORDER BY id ASC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|row| {
+                let kind: String = row.get("kind");
+                Some(BlocklistEntry {
+                    id: Some(row.get("id")),
+                    kind: BlockKind::from_db(&kind)?,
+                    value: row.get("value"),
+                    reason: row.get("reason"),
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    pub async fn delete_block(&self, id: i64) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM blocklist WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Search cache operations
+    pub async fn get_cached_response(&self, kind: CacheKind, key: &str, max_age_secs: u64) -> Result<Option<String>> {
+        let row = sqlx::query(
+            "SELECT response FROM search_cache WHERE kind = ? AND cache_key = ? AND created_at > datetime('now', ?)"
+        )
+        .bind(kind.as_str())
+        .bind(key)
+        .bind(format!("-{} seconds", max_age_secs))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| row.get("response")))
+    }
+
+    pub async fn store_cached_response(&self, kind: CacheKind, key: &str, response: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO search_cache (kind, cache_key, response) VALUES (?, ?, ?)
+            ON CONFLICT (kind, cache_key) DO UPDATE SET response = excluded.response, created_at = CURRENT_TIMESTAMP
+            "#
+        )
+        .bind(kind.as_str())
+        .bind(key)
+        .bind(response)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_search_cache(&self) -> Result<Vec<CacheEntry>> {
+        let rows = sqlx::query(
+            "SELECT kind, cache_key, length(response) AS size_bytes, created_at FROM search_cache ORDER BY created_at DESC, kind, cache_key"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|row| {
+                let kind: String = row.get("kind");
+                Some(CacheEntry {
+                    kind: CacheKind::from_db(&kind)?,
+                    key: row.get("cache_key"),
+                    size_bytes: row.get("size_bytes"),
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Deletes cached responses of one kind, or all of them, returning how many were removed.
+    pub async fn purge_search_cache(&self, kind: Option<CacheKind>) -> Result<u64> {
+        let result = match kind {
+            Some(kind) => sqlx::query("DELETE FROM search_cache WHERE kind = ?")
+                .bind(kind.as_str())
+                .execute(&self.pool)
+                .await?,
+            None => sqlx::query("DELETE FROM search_cache").execute(&self.pool).await?,
+        };
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_expired_cache(&self, max_age_secs: u64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM search_cache WHERE created_at <= datetime('now', ?)")
+            .bind(format!("-{} seconds", max_age_secs))
             .execute(&self.pool)
             .await?;
+        Ok(result.rows_affected())
+    }
+
+    // API quota operations
+    pub async fn record_api_call(&self, day: &str, call: &str, units: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO api_quota (day, call, count, units) VALUES (?, ?, 1, ?)
+            ON CONFLICT (day, call) DO UPDATE SET count = count + 1, units = units + excluded.units
+            "#
+        )
+        .bind(day)
+        .bind(call)
+        .bind(units)
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
+
+    /// Returns `(call, count, units)` for each call type made on `day`.
+    pub async fn get_api_calls(&self, day: &str) -> Result<Vec<(String, i64, i64)>> {
+        let rows = sqlx::query("SELECT call, count, units FROM api_quota WHERE day = ? ORDER BY units DESC, call")
+            .bind(day)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("call"), row.get("count"), row.get("units")))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    /// A database file of its own for one test, deleted with its journal files when dropped.
+    pub(crate) struct TempDatabase(pub PathBuf);
+
+    impl TempDatabase {
+        pub fn new() -> Self {
+            TempDatabase(std::env::temp_dir().join(format!("rust-sr-test-{}.db", uuid::Uuid::new_v4().simple())))
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm", "-journal"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn song(title: &str, video_id: &str, user: &str) -> YouTubeURL {
+        YouTubeURL {
+            id: None,
+            title: title.to_string(),
+            url: format!("https://www.youtube.com/watch?v={}", video_id),
+            user: user.to_string(),
+            created_at: None,
+            duration_seconds: Some(200),
+            channel_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_limits_are_counted_in_the_insert() {
+        let file = TempDatabase::new();
+        let db = Database::new(&file.0).await.unwrap();
+
+        assert!(db.add_song_within(&song("One", "v1", "ann"), Some(1), None).await.unwrap().is_some());
+        // Users are counted ignoring case and surrounding spaces
+        assert!(db.add_song_within(&song("Two", "v2", " ANN "), Some(1), None).await.unwrap().is_none());
+        assert!(db.add_song_within(&song("Two", "v2", "bob"), Some(1), Some(2)).await.unwrap().is_some());
+        assert!(db.add_song_within(&song("Three", "v3", "cat"), Some(1), Some(2)).await.unwrap().is_none());
+        // Other rooms have queues of their own
+        assert!(db.room("party").add_song_within(&song("Three", "v3", "ann"), Some(1), Some(2)).await.unwrap().is_some());
+        assert_eq!(db.count_songs().await.unwrap(), 2);
+    }
+
+    #[test]
+    fn room_names_are_checked_and_lowercased() {
+        assert_eq!(room_name("Party_Room-2").as_deref(), Some("party_room-2"));
+        assert_eq!(room_name(&"a".repeat(64)), Some("a".repeat(64)));
+        assert_eq!(room_name(&"a".repeat(65)), None);
+        assert_eq!(room_name(""), None);
+        assert_eq!(room_name("two words"), None);
+        assert_eq!(room_name("../etc"), None);
+        assert_eq!(room_name("café"), None);
+    }
+
+    #[tokio::test]
+    async fn title_filter_matches_wildcards_literally() {
+        let file = TempDatabase::new();
+        let db = Database::new(&file.0).await.unwrap();
+        for (title, video_id) in [("100% Pure", "v1"), ("100 Pure", "v2"), ("a_b", "v3"), ("axb", "v4"), ("back\\slash", "v5")] {
+            db.add_song(&song(title, video_id, "ann")).await.unwrap();
+        }
+
+        let titles = |q: &str| {
+            let query = ListQuery { q: Some(q.to_string()), ..Default::default() };
+            let db = db.clone();
+            async move {
+                let (songs, total) = db.get_songs_page(&query).await.unwrap();
+                assert_eq!(total, songs.len() as i64);
+                songs.into_iter().map(|song| song.title).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(titles("100%").await, ["100% Pure"]);
+        assert_eq!(titles("a_b").await, ["a_b"]);
+        assert_eq!(titles("k\\s").await, ["back\\slash"]);
+        assert_eq!(titles("PURE").await, ["100% Pure", "100 Pure"]);
+        assert_eq!(titles("%").await, ["100% Pure"]);
+    }
+
+    #[tokio::test]
+    async fn unused_room_buses_are_dropped() {
+        let file = TempDatabase::new();
+        let db = Database::new(&file.0).await.unwrap();
+        let rooms = |db: &Database| {
+            let mut rooms: Vec<String> = db.buses.lock().unwrap().keys().cloned().collect();
+            rooms.sort();
+            rooms
+        };
+
+        for room in ["made-up-1", "made-up-2", "made-up-3"] {
+            db.room(room);
+        }
+        let party = db.room("party");
+        let mut events = db.room("stream").events().subscribe();
+        db.room("made-up-4");
+        assert_eq!(rooms(&db), ["default", "made-up-4", "party", "stream"]);
+
+        // Handles and subscribers of a room share its bus for as long as either is around
+        db.room("stream").add_song(&song("One", "v1", "ann")).await.unwrap();
+        assert!(matches!(events.recv().await.unwrap(), QueueEvent::SongAdded { .. }));
+        drop((party, events));
+        db.room("other");
+        assert_eq!(rooms(&db), ["default", "other"]);
+    }
 }
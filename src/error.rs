@@ -0,0 +1,234 @@
+use serde::Serialize;
+use std::convert::Infallible;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::reply::{json, with_status};
+use warp::{Rejection, Reply};
+use crate::blocklist::Blocked;
+use crate::limits::{LengthRejected, LimitExceeded};
+use crate::quota::QuotaReserved;
+
+/// Everything a route can fail with. Handlers reject with it and `handle_rejection` turns it
+/// into a JSON body of the form `{"error": "<message>", "code": "<code>"}` with a matching status.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("That is not a valid YouTube URL")]
+    InvalidUrl,
+    #[error("Host authentication required")]
+    Unauthorized,
+    #[error("Only the host can do that")]
+    HostOnly,
+    #[error("{}", .0.message())]
+    Blocked(Blocked),
+    #[error("{0}")]
+    NotFound(String),
+    // A title request whose search found nothing
+    #[error("No song matches that title")]
+    NoMatch,
+    #[error("That song is already in the queue")]
+    AlreadyQueued,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{}", .0.message())]
+    BadLength(LengthRejected),
+    #[error("{}", .0.message())]
+    Limited(LimitExceeded),
+    // YouTube (or the configured provider) failed
+    #[error("{0}")]
+    Upstream(String),
+    #[error("{0}, try again after midnight Pacific time")]
+    QuotaReserved(QuotaReserved),
+    #[error("{0}")]
+    Internal(String),
+}
+
+// warp turns any `Reject` into a `Rejection`, so handlers can `?` or `.into()` it
+impl warp::reject::Reject for ApiError {}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    // One of the codes of `ApiError::code`, or a code of warp's own rejections
+    pub code: String,
+    // Only for `rate_limited`, when waiting helps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+}
+
+impl ApiError {
+    /// Logs the underlying error and reports only `message` to the client.
+    pub fn internal<E: std::fmt::Debug>(message: &'static str) -> impl FnOnce(E) -> ApiError {
+        move |e| {
+            tracing::error!(error = ?e, "{}", message);
+            ApiError::Internal(message.to_string())
+        }
+    }
+
+    /// Like `internal`, for failures of the music provider.
+    pub fn upstream<E: std::fmt::Debug>(message: &'static str) -> impl FnOnce(E) -> ApiError {
+        move |e| {
+            tracing::warn!(error = ?e, "{}", message);
+            ApiError::Upstream(message.to_string())
+        }
+    }
+
+    /// Like `upstream`, but tells a call held back for the quota reserve from a failed one.
+    pub fn provider(message: &'static str) -> impl FnOnce(anyhow::Error) -> ApiError {
+        move |e| match e.downcast::<QuotaReserved>() {
+            Ok(reserved) => ApiError::QuotaReserved(reserved),
+            Err(e) => ApiError::upstream(message)(e),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidUrl => StatusCode::BAD_REQUEST,
+            // The host pages ask for the password on 401
+            ApiError::Unauthorized | ApiError::HostOnly => StatusCode::UNAUTHORIZED,
+            ApiError::Blocked(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) | ApiError::NoMatch => StatusCode::NOT_FOUND,
+            ApiError::AlreadyQueued | ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::BadLength(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Limited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::QuotaReserved(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable, machine-readable name of the error for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidUrl => "invalid_url",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::HostOnly => "host_only",
+            ApiError::Blocked(_) => "blocked",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::NoMatch => "no_match",
+            ApiError::AlreadyQueued => "already_queued",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::BadLength(_) => "song_length",
+            ApiError::Limited(_) => "rate_limited",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::QuotaReserved(_) => "quota_reserved",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn to_response(&self) -> warp::reply::Response {
+        let ApiError::Limited(exceeded) = self else {
+            return status_response(self.status(), self.code(), &self.to_string());
+        };
+
+        let retry_after = exceeded.retry_after_secs();
+        let reply = with_status(
+            json(&ErrorBody {
+                error: self.to_string(),
+                code: self.code().to_string(),
+                retry_after_seconds: retry_after,
+            }),
+            self.status(),
+        );
+        match retry_after {
+            Some(secs) => warp::reply::with_header(reply, "retry-after", secs.to_string()).into_response(),
+            None => reply.into_response(),
+        }
+    }
+}
+
+/// Answers every rejected request with a JSON error, including warp's own rejections.
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Infallible> {
+    let error = if let Some(error) = err.find::<ApiError>() {
+        return Ok(error.to_response());
+    } else if err.is_not_found() {
+        ApiError::NotFound("Not found".to_string())
+    } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        ApiError::BadRequest(format!("Invalid request body: {}", e))
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        ApiError::BadRequest(format!("Invalid query string: {}", e))
+    } else if let Some(e) = err.find::<warp::reject::MissingHeader>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::InvalidHeader>() {
+        ApiError::BadRequest(e.to_string())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        return Ok(status_response(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Request body is too large"));
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        return Ok(status_response(StatusCode::LENGTH_REQUIRED, "length_required", "A content-length header is required"));
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        return Ok(status_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            "Expected a JSON request body",
+        ));
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "Method not allowed"));
+    } else {
+        tracing::error!(rejection = ?err, "Unhandled rejection");
+        ApiError::Internal("Internal server error".to_string())
+    };
+    Ok(error.to_response())
+}
+
+fn status_response(status: StatusCode, code: &str, message: &str) -> warp::reply::Response {
+    with_status(
+        json(&ErrorBody {
+            error: message.to_string(),
+            code: code.to_string(),
+            retry_after_seconds: None,
+        }),
+        status,
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn answer(rejection: Rejection) -> (StatusCode, warp::http::HeaderMap, serde_json::Value) {
+        let response = handle_rejection(rejection).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = warp::hyper::body::to_bytes(body).await.unwrap();
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejections_answer_with_their_status_and_code() {
+        let cases = [
+            (warp::reject::custom(ApiError::InvalidUrl), StatusCode::BAD_REQUEST, "invalid_url"),
+            (warp::reject::custom(ApiError::NoMatch), StatusCode::NOT_FOUND, "no_match"),
+            (warp::reject::custom(ApiError::AlreadyQueued), StatusCode::CONFLICT, "already_queued"),
+            (warp::reject::custom(ApiError::Upstream("YouTube is down".to_string())), StatusCode::BAD_GATEWAY, "upstream_error"),
+            (warp::reject::custom(ApiError::QuotaReserved(QuotaReserved)), StatusCode::SERVICE_UNAVAILABLE, "quota_reserved"),
+            (warp::reject::custom(ApiError::Internal("Database error".to_string())), StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+            (warp::reject::not_found(), StatusCode::NOT_FOUND, "not_found"),
+        ];
+        for (rejection, status, code) in cases {
+            let (answered, _, body) = answer(rejection).await;
+            assert_eq!((answered, body["code"].as_str()), (status, Some(code)));
+        }
+    }
+
+    #[tokio::test]
+    async fn cooldowns_say_when_to_retry() {
+        let limited = ApiError::Limited(LimitExceeded::Cooldown { retry_after: Duration::from_millis(2500) });
+        let (status, headers, body) = answer(warp::reject::custom(limited)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers["retry-after"], "2");
+        assert_eq!(body["retry_after_seconds"], 2);
+    }
+
+    #[test]
+    fn held_back_calls_are_not_upstream_failures() {
+        let reserved = anyhow::Error::new(QuotaReserved).context("Error finding recommendation");
+        assert!(matches!(ApiError::provider("Error finding recommendation")(reserved), ApiError::QuotaReserved(_)));
+
+        let failed = anyhow::anyhow!("YouTube API request failed with status: 500");
+        assert!(matches!(ApiError::provider("Error finding recommendation")(failed), ApiError::Upstream(_)));
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use serde::Serialize;
+use utoipa::ToSchema;
+use tokio::sync::broadcast;
+use crate::models::*;
+
+/// Events pushed to `/events` subscribers whenever queue state changes.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueEvent {
+    SongAdded { song: YouTubeURL },
+    SongRemoved { id: i64, url: String },
+    // Main queue ids in their new play order
+    QueueReordered { order: Vec<i64> },
+    PlaylistSongAdded { song: PlaylistQueueItem },
+    PlaylistSongRemoved { id: i64 },
+    PlaylistQueueCleared,
+    NowPlayingChanged { now_playing: NowPlaying },
+    SkipVoteChanged { votes: usize, required: usize },
+    // Enough listeners voted to skip; the host player should advance
+    SkipRequested { now_playing_id: i64 },
+    // Sent to a subscriber that fell behind; it should re-fetch the queues
+    Lagged { missed: u64 },
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    // Counted to tell whether a `Database` handle still holds the bus
+    sender: Arc<broadcast::Sender<QueueEvent>>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender: Arc::new(sender) }
+    }
+
+    /// Whether nothing but this value holds the bus: no subscriber and no other clone to publish on.
+    pub fn is_unused(&self) -> bool {
+        Arc::strong_count(&self.sender) == 1 && self.sender.receiver_count() == 0
+    }
+
+    pub fn publish(&self, event: QueueEvent) {
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Number of connected `/events` clients, used as the count of active listeners.
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -1,195 +1,608 @@
 use warp::reply::{json, with_status};
 use warp::{Rejection, Reply};
-use crate::database::Database;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::StreamExt;
+use crate::auth::HostAuth;
+use crate::blocklist::Blocklist;
+use crate::cache::SearchCache;
+use crate::database::{is_unique_violation, Database};
+use crate::error::{ApiError, ErrorBody};
+use crate::events::QueueEvent;
+use crate::limits::RequestLimiter;
+use crate::quota::{QuotaReserved, QuotaTracker, QuotaUsage};
+use crate::skip::{SkipVotes, VoteOutcome, VoteTally};
+use crate::transfer::{self, TransferFormat, TransferTarget};
+use crate::provider::{MusicProvider, Provider, SEARCH_CANDIDATES};
+use crate::recommend::{recommend, RecommendationSettings, SharedSettings};
 use crate::youtube::YouTubeAPI;
 use crate::models::*;
 use anyhow::Result;
+use std::collections::HashSet;
+use std::path::PathBuf;
 
-pub async fn requester_handler() -> Result<impl Reply, Rejection> {
-    match std::fs::read_to_string("static/requester.html") {
-        Ok(content) => Ok(warp::reply::html(content)),
-        Err(_) => Ok(warp::reply::html("Error loading requester page".to_string())),
+// Candidates shown by /search unless `limit` asks for more
+const DEFAULT_SEARCH_RESULTS: usize = 5;
+
+pub async fn requester_handler(static_dir: PathBuf) -> Result<impl Reply, Rejection> {
+    let content = std::fs::read_to_string(static_dir.join("requester.html"))
+        .map_err(ApiError::internal("Error loading requester page"))?;
+    Ok(warp::reply::html(content))
+}
+
+pub async fn host_handler(static_dir: PathBuf) -> Result<impl Reply, Rejection> {
+    let content = std::fs::read_to_string(static_dir.join("host.html"))
+        .map_err(ApiError::internal("Error loading host page"))?;
+    Ok(warp::reply::html(content))
+}
+
+// Queueing fails on the unique (room, url) when the song is already waiting
+fn queue_error(e: anyhow::Error) -> ApiError {
+    if is_unique_violation(&e) {
+        ApiError::AlreadyQueued
+    } else {
+        ApiError::internal("Error adding song to the queue")(e)
     }
 }
 
-pub async fn host_handler() -> Result<impl Reply, Rejection> {
-    match std::fs::read_to_string("static/host.html") {
-        Ok(content) => Ok(warp::reply::html(content)),
-        Err(_) => Ok(warp::reply::html("Error loading host page".to_string())),
+/// Validates, resolves and queues a song request. Shared by `POST /url` and the chat bot.
+pub async fn request_song(
+    db: &Database,
+    provider: &dyn MusicProvider,
+    limiter: &RequestLimiter,
+    is_host: bool,
+    request: AddSongRequest,
+) -> Result<YouTubeURL, ApiError> {
+    let user = request.user.clone();
+    let result = queue_request(db, provider, limiter, is_host, request).await;
+    if let Err(e) = &result {
+        tracing::info!(user = %user, room = db.room_name(), code = e.code(), "Song request refused: {}", e);
     }
+    #[cfg(feature = "metrics")]
+    crate::metrics::song_requested(result.as_ref().err());
+    result
 }
 
-pub async fn add_song(
-    db: Database,
-    youtube_api: YouTubeAPI,
+async fn queue_request(
+    db: &Database,
+    provider: &dyn MusicProvider,
+    limiter: &RequestLimiter,
+    is_host: bool,
     request: AddSongRequest,
-) -> Result<impl Reply, Rejection> {
-    let song = if let Some(url) = request.url {
+) -> Result<YouTubeURL, ApiError> {
+    if request.play_next && !is_host {
+        return Err(ApiError::HostOnly);
+    }
+
+    // The host is never rate limited
+    if !is_host {
+        match limiter.check(db, &request.user).await {
+            Ok(Some(exceeded)) => return Err(ApiError::Limited(exceeded)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = ?e, "Error checking request limits"),
+        }
+    }
+
+    let blocklist = Blocklist::load(db).await.unwrap_or_else(|e| {
+        tracing::warn!(error = ?e, "Error loading blocklist");
+        Blocklist::default()
+    });
+    blocklist.check_user(&request.user).map_err(ApiError::Blocked)?;
+
+    let play_next = request.play_next;
+    let direct = match (request.video_id, request.url) {
+        // A candidate picked from /search
+        (Some(video_id), _) => Some((video_id.trim().to_string(), None)),
+        (None, Some(url)) => Some((YouTubeAPI::extract_video_id(&url).ok_or(ApiError::InvalidUrl)?, Some(url))),
+        (None, None) => None,
+    };
+    let (song, video_id, channel_id) = if let Some((video_id, url)) = direct {
         // Direct URL addition
-        let _video_id = YouTubeAPI::extract_video_id(&url)
-            .ok_or_else(|| warp::reject::custom(Error::InvalidUrl))?;
+        if video_id.is_empty() {
+            return Err(ApiError::InvalidUrl);
+        }
+        let mut videos = provider.get_videos(std::slice::from_ref(&video_id)).await
+            .map_err(ApiError::upstream("Could not look up that video"))?;
+        // The video does not exist or is private
+        let video = videos.remove(&video_id).ok_or(ApiError::InvalidUrl)?;
+        // The requester picks the displayed title, so check the real one too
+        blocklist
+            .check_song(&video_id, video.channel_id.as_deref(), &video.title)
+            .map_err(ApiError::Blocked)?;
         
-        YouTubeURL {
+        // Picked candidates and untitled URLs get the video's own title
+        let title = match url {
+            Some(_) if !request.title.trim().is_empty() => request.title,
+            _ => video.title.clone(),
+        };
+        let song = YouTubeURL {
             id: None,
-            title: request.title,
-            url,
+            title,
+            url: url.unwrap_or_else(|| format!("https://www.youtube.com/watch?v={}", video_id)),
             user: request.user,
             created_at: None,
-        }
+            duration_seconds: Some(video.duration_seconds),
+            channel_id: video.channel_id.clone(),
+        };
+        (song, video_id, video.channel_id)
     } else {
         // Search for song
-        let search_result = youtube_api.search_song(&request.title).await
-            .map_err(|e| {
-                eprintln!("YouTube search error: {:?}", e);
-                warp::reject::custom(Error::YouTubeSearchFailed)
-            })?;
+        let search_result = provider.search_song(&request.title).await
+            .map_err(ApiError::upstream("Could not search for that song"))?
+            .ok_or(ApiError::NoMatch)?;
         
-        YouTubeURL {
+        let song = YouTubeURL {
             id: None,
             title: search_result.title,
             url: search_result.url,
             user: request.user,
             created_at: None,
+            duration_seconds: search_result.duration_seconds,
+            channel_id: search_result.channel_id.clone(),
+        };
+        (song, search_result.video_id, search_result.channel_id)
+    };
+
+    blocklist
+        .check_song(&video_id, channel_id.as_deref(), &song.title)
+        .map_err(ApiError::Blocked)?;
+
+    if !is_host {
+        if let Some(seconds) = song.duration_seconds {
+            limiter.song_length().check(seconds).map_err(ApiError::BadLength)?;
         }
+    }
+
+    let id = if is_host {
+        db.add_song(&song).await.map_err(queue_error)?
+    } else {
+        limiter
+            .add_song(db, &song)
+            .await
+            .map_err(queue_error)?
+            .map_err(ApiError::Limited)?
     };
+    limiter.record(db.room_name(), &song.user);
 
-    match db.add_song(&song).await {
-        Ok(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "message": format!("Song added successfully: {} by {}", song.title, song.user)
-            })),
-            warp::http::StatusCode::CREATED,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "error": "URL already exists or error inserting URL"
-            })),
-            warp::http::StatusCode::CONFLICT,
-        )),
+    if play_next {
+        if let Err(e) = db.move_song(id, 0).await {
+            tracing::warn!(error = ?e, "Error moving song to the front of the queue");
+        }
+    }
+    tracing::info!(user = %song.user, video_id = %video_id, room = db.room_name(), play_next, "Song queued: {}", song.title);
+    Ok(song)
+}
+
+#[utoipa::path(
+    post, path = "/url", tag = "queue",
+    request_body = AddSongRequest,
+    responses(
+        (status = 201, description = "Song queued", body = MessageResponse),
+        (status = 400, description = "Not a YouTube video URL", body = ErrorBody),
+        (status = 401, description = "`play_next` without a host session", body = ErrorBody),
+        (status = 403, description = "Blocked song, channel or requester", body = ErrorBody),
+        (status = 404, description = "No song matches the title", body = ErrorBody),
+        (status = 409, description = "Song already queued", body = ErrorBody),
+        (status = 422, description = "Song too short or too long", body = ErrorBody),
+        (status = 429, description = "Request limit reached", body = ErrorBody),
+        (status = 502, description = "YouTube lookup failed", body = ErrorBody),
+    )
+)]
+pub async fn add_song(
+    db: Database,
+    provider: Provider,
+    limiter: RequestLimiter,
+    is_host: bool,
+    request: AddSongRequest,
+) -> Result<impl Reply, Rejection> {
+    let song = request_song(&db, &*provider, &limiter, is_host, request).await?;
+    Ok(with_status(
+        json(&MessageResponse::new(format!("Song added successfully: {} by {}", song.title, song.user))),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+/// Lists search candidates for the requester to pick from. Blocked songs are left out, and so are
+/// songs outside the length limits unless the host is searching. Requesters are limited per address,
+/// and nobody can search once the quota is down to the reserve; requests by title still work then.
+#[utoipa::path(
+    get, path = "/search", tag = "queue",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Candidates to pass as `video_id` to `POST /url`", body = Vec<SearchCandidate>),
+        (status = 400, description = "Empty search query", body = ErrorBody),
+        (status = 429, description = "Too many searches from this address", body = ErrorBody),
+        (status = 502, description = "YouTube search failed", body = ErrorBody),
+        (status = 503, description = "Paused to keep the API quota for song requests", body = ErrorBody),
+    )
+)]
+pub async fn search_songs(
+    db: Database,
+    provider: Provider,
+    limiter: RequestLimiter,
+    quota: QuotaTracker,
+    is_host: bool,
+    client: Option<std::net::SocketAddr>,
+    query: SearchQuery,
+) -> Result<impl Reply, Rejection> {
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(ApiError::BadRequest("Search query must not be empty".to_string()).into());
     }
+    if !is_host {
+        let client = client.map(|addr| addr.ip().to_string()).unwrap_or_default();
+        limiter.search(&client).map_err(ApiError::Limited)?;
+    }
+    if !quota.above_reserve().await {
+        return Err(ApiError::QuotaReserved(QuotaReserved).into());
+    }
+
+    let candidates = provider
+        .search_songs(q)
+        .await
+        .map_err(ApiError::upstream("Error searching for songs"))?;
+
+    let blocklist = Blocklist::load(&db).await.unwrap_or_default();
+    let candidates: Vec<SearchCandidate> = candidates
+        .into_iter()
+        .filter(|candidate| {
+            blocklist
+                .check_song(&candidate.video_id, candidate.channel_id.as_deref(), &candidate.title)
+                .is_ok()
+        })
+        .filter(|candidate| is_host || candidate.duration_seconds.is_none_or(|seconds| limiter.song_length().allows(seconds)))
+        .take(query.limit.unwrap_or(DEFAULT_SEARCH_RESULTS).clamp(1, SEARCH_CANDIDATES))
+        .collect();
+
+    Ok(json(&candidates))
 }
 
+#[utoipa::path(
+    post, path = "/url/{id}/move", tag = "queue",
+    params(("id" = i64, Path, description = "Queue id of the song")),
+    request_body = MoveSongRequest,
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Song moved", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "Song not in the queue", body = ErrorBody),
+    )
+)]
+pub async fn move_song(db: Database, id: i64, position: usize) -> Result<impl Reply, Rejection> {
+    if !db.move_song(id, position).await.map_err(ApiError::internal("Error moving song"))? {
+        return Err(ApiError::NotFound("Song not found in queue".to_string()).into());
+    }
+    Ok(json(&MessageResponse::new("Song moved successfully")))
+}
+
+#[utoipa::path(
+    post, path = "/url/{id}/bump", tag = "queue",
+    params(("id" = i64, Path, description = "Queue id of the song")),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Song moved to the front of the queue", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "Song not in the queue", body = ErrorBody),
+    )
+)]
+pub async fn bump_song(db: Database, id: i64) -> Result<impl Reply, Rejection> {
+    move_song(db, id, 0).await
+}
+
+#[utoipa::path(
+    delete, path = "/url", tag = "queue",
+    request_body = DeleteSongRequest,
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Song removed", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "URL not in the queue", body = ErrorBody),
+    )
+)]
 pub async fn delete_song(
     db: Database,
     request: DeleteSongRequest,
 ) -> Result<impl Reply, Rejection> {
-    match db.delete_song_by_url(&request.url).await {
-        Ok(true) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "message": "URL deleted successfully"
-            })),
-            warp::http::StatusCode::OK,
-        )),
-        Ok(false) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "error": "URL not found"
-            })),
-            warp::http::StatusCode::NOT_FOUND,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "error": "Error deleting URL"
-            })),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
+    if !db.delete_song_by_url(&request.url).await.map_err(ApiError::internal("Error deleting URL"))? {
+        return Err(ApiError::NotFound("URL not found".to_string()).into());
     }
+    Ok(json(&MessageResponse::new("URL deleted successfully")))
 }
 
-pub async fn get_oldest_song(db: Database, youtube_api: YouTubeAPI, playlist_id: Option<i64>) -> Result<impl Reply, Rejection> {
-    match db.get_oldest_song().await {
-        Ok(Some(song)) => {
-            // Delete the song after retrieving it
-            let _ = db.delete_song_by_id(song.id.unwrap()).await;
-            Ok(json(&song))
-        }
-        Ok(None) => {
-            // Main queue is empty, try to get a song from playlist queue
-            match db.get_oldest_playlist_song().await {
-                Ok(Some(playlist_song)) => {
-                    // Delete the song from playlist queue after retrieving it
-                    let _ = db.delete_playlist_song_by_id(playlist_song.id.unwrap()).await;
-                    
-                    // Convert to YouTubeURL format
-                    let song = YouTubeURL {
-                        id: None,
-                        title: playlist_song.title,
-                        url: playlist_song.url,
-                        user: "Playlist".to_string(),
-                        created_at: None,
-                    };
-                    Ok(json(&song))
-                }
-                Ok(None) => {
-                    // Both queues are empty, get a recommendation
-                    match youtube_api.get_recommendation(&[]).await {
-                        Ok(recommendation) => {
-                            // Store the recommendation in the database
-                            let _ = db.store_recommendation(&recommendation.video_id).await;
-                            
-                            // Convert to YouTubeURL format
-                            let song = YouTubeURL {
-                                id: None,
-                                title: recommendation.title,
-                                url: recommendation.url,
-                                user: "System".to_string(),
-                                created_at: None,
-                            };
-                            Ok(json(&song))
-                        }
-                        Err(_) => Ok(json(&serde_json::json!({
-                            "error": "No songs available and no recommendations found"
-                        }))),
-                    }
-                }
-                Err(_) => Ok(json(&serde_json::json!({
-                    "error": "Error fetching playlist song"
-                }))),
+#[utoipa::path(
+    get, path = "/url/oldest", tag = "queue",
+    params(("playlist_id" = Option<i64>, Query, description = "Saved playlist to refill the playlist queue from once both queues are empty")),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "The song to play next, removed from its queue", body = YouTubeURL),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "Nothing queued and no recommendation found", body = ErrorBody),
+    )
+)]
+pub async fn get_oldest_song(
+    db: Database,
+    provider: Provider,
+    settings: SharedSettings,
+    playlist_id: Option<i64>,
+) -> Result<impl Reply, Rejection> {
+    if let Some(song) = db.get_oldest_song().await.map_err(ApiError::internal("Error fetching URL"))? {
+        // Delete the song after retrieving it
+        let _ = db.delete_song_by_id(song.id.unwrap()).await;
+        let video_id = YouTubeAPI::extract_video_id(&song.url);
+        record_now_playing(&db, &song, video_id.as_deref(), PlaySource::MainQueue).await;
+        return Ok(json(&song));
+    }
+
+    // Main queue is empty, try to get a song from playlist queue
+    let mut next = db.get_oldest_playlist_song().await;
+    if let (Ok(None), Some(playlist_id)) = (&next, playlist_id) {
+        // Refill from the selected playlist before falling back to recommendations
+        refill_playlist_queue(&db, &*provider, playlist_id).await;
+        next = db.get_oldest_playlist_song().await;
+    }
+
+    if let Some(playlist_song) = next.map_err(ApiError::internal("Error fetching playlist song"))? {
+        // Delete the song from playlist queue after retrieving it
+        let _ = db.delete_playlist_song_by_id(playlist_song.id.unwrap()).await;
+
+        // The selected playlist takes the credit, otherwise the one the song was queued from
+        if let Some(progress_id) = playlist_id.or(playlist_song.playlist_id) {
+            if let Err(e) = db
+                .mark_song_played(progress_id, &playlist_song.video_id, &playlist_song.title, &playlist_song.url)
+                .await
+            {
+                tracing::warn!(error = ?e, video_id = %playlist_song.video_id, "Error recording playlist progress");
             }
         }
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching URL"
-        }))),
+
+        // Convert to YouTubeURL format
+        let song = YouTubeURL {
+            id: None,
+            title: playlist_song.title,
+            url: playlist_song.url,
+            user: "Playlist".to_string(),
+            created_at: None,
+            duration_seconds: playlist_song.duration_seconds,
+            channel_id: None,
+        };
+        record_now_playing(&db, &song, Some(&playlist_song.video_id), PlaySource::PlaylistQueue).await;
+        return Ok(json(&song));
     }
+
+    // Both queues are empty, get a recommendation
+    let recommendation = recommend(&db, &*provider, &settings).await.map_err(|e| {
+        tracing::warn!(error = ?e, "Error finding recommendation");
+        ApiError::NotFound("No songs available and no recommendations found".to_string())
+    })?;
+    // Convert to YouTubeURL format
+    let song = YouTubeURL {
+        id: None,
+        title: recommendation.title,
+        url: recommendation.url,
+        user: "System".to_string(),
+        created_at: None,
+        duration_seconds: recommendation.duration_seconds,
+        channel_id: recommendation.channel_id,
+    };
+    record_now_playing(&db, &song, Some(&recommendation.video_id), PlaySource::Recommendation).await;
+    Ok(json(&song))
 }
 
-pub async fn get_all_songs(db: Database) -> Result<impl Reply, Rejection> {
-    match db.get_all_songs().await {
-        Ok(songs) => Ok(json(&songs)),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching URLs"
-        }))),
+async fn record_now_playing(db: &Database, song: &YouTubeURL, video_id: Option<&str>, source: PlaySource) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::song_played(source);
+    tracing::info!(
+        user = %song.user,
+        video_id = video_id.unwrap_or_default(),
+        room = db.room_name(),
+        source = source.as_str(),
+        "Now playing: {}",
+        song.title
+    );
+    if let Err(e) = db.record_play(song, video_id, source).await {
+        tracing::error!(error = ?e, "Error recording play history");
     }
 }
 
+#[utoipa::path(
+    get, path = "/now-playing", tag = "history",
+    responses((status = 200, description = "The song on air, null before the first song", body = Option<NowPlaying>))
+)]
+pub async fn get_now_playing(db: Database) -> Result<impl Reply, Rejection> {
+    let now_playing = db.get_now_playing().await.map_err(ApiError::internal("Error fetching now playing"))?;
+    Ok(json(&now_playing))
+}
+
+#[utoipa::path(
+    get, path = "/history", tag = "history",
+    params(HistoryQuery),
+    responses((status = 200, description = "Played songs, most recent first", body = Vec<NowPlaying>))
+)]
+pub async fn get_history(db: Database, query: HistoryQuery) -> Result<impl Reply, Rejection> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let history = db
+        .get_play_history(limit, offset)
+        .await
+        .map_err(ApiError::internal("Error fetching play history"))?;
+    Ok(json(&history))
+}
+
+#[utoipa::path(
+    get, path = "/urls", tag = "queue",
+    params(ListQuery),
+    responses((status = 200, description = "Songs of the main queue, in play order unless sorted otherwise", body = Vec<YouTubeURL>,
+        headers(("x-total-count" = i64, description = "Songs matching the filters"))))
+)]
+pub async fn get_all_songs(db: Database, query: ListQuery) -> Result<impl Reply, Rejection> {
+    let (songs, total) = db
+        .get_songs_page(&list_query(query))
+        .await
+        .map_err(ApiError::internal("Error fetching URLs"))?;
+    Ok(page_reply(&songs, total))
+}
+
+// Clamps the paging of a list query to what the list routes serve
+fn list_query(mut query: ListQuery) -> ListQuery {
+    query.limit = query.limit.map(|limit| limit.clamp(1, 1000));
+    query.offset = query.offset.map(|offset| offset.max(0));
+    query
+}
+
+fn page_reply<T: serde::Serialize>(items: &[T], total: i64) -> impl Reply {
+    warp::reply::with_header(json(&items), "x-total-count", total.to_string())
+}
+
+/// Rooms with songs queued or played, plus those configured in `[rooms]`.
+#[utoipa::path(
+    get, path = "/rooms", tag = "rooms",
+    responses((status = 200, description = "Rooms in use or configured", body = Vec<RoomSummary>))
+)]
+pub async fn get_rooms(db: Database, settings: SharedSettings) -> Result<impl Reply, Rejection> {
+    let mut rooms = db.get_rooms().await.map_err(ApiError::internal("Error fetching rooms"))?;
+    let configured: Vec<String> = settings.read().unwrap().rooms.keys().cloned().collect();
+    for room in configured {
+        if !rooms.iter().any(|summary| summary.room == room) {
+            rooms.push(RoomSummary {
+                room,
+                queue_length: 0,
+                playlist_queue_length: 0,
+                last_played_at: None,
+            });
+        }
+    }
+    rooms.sort_by(|a, b| a.room.cmp(&b.room));
+    Ok(json(&rooms))
+}
+
+#[utoipa::path(
+    get, path = "/recommendation", tag = "recommendations",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "A recommended song, not queued", body = YouTubeURL),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 502, description = "No recommendation could be found", body = ErrorBody),
+        (status = 503, description = "Paused to keep the API quota for song requests", body = ErrorBody),
+    )
+)]
 pub async fn get_recommendation(
     db: Database,
-    youtube_api: YouTubeAPI,
+    provider: Provider,
+    settings: SharedSettings,
 ) -> Result<impl Reply, Rejection> {
-    let recent_recommendations = db.get_recent_recommendations().await
-        .unwrap_or_default();
-
-    match youtube_api.get_recommendation(&recent_recommendations).await {
-        Ok(recommendation) => {
-            // Store the recommendation
-            let _ = db.store_recommendation(&recommendation.video_id).await;
-            
-            let song = YouTubeURL {
-                id: None,
-                title: recommendation.title,
-                url: recommendation.url,
-                user: "Recommended".to_string(),
-                created_at: None,
-            };
-            
-            Ok(json(&song))
-        }
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error finding recommendation"
-        }))),
+    let recommendation = recommend(&db, &*provider, &settings)
+        .await
+        .map_err(ApiError::provider("Error finding recommendation"))?;
+    let song = YouTubeURL {
+        id: None,
+        title: recommendation.title,
+        url: recommendation.url,
+        user: "Recommended".to_string(),
+        created_at: None,
+        duration_seconds: recommendation.duration_seconds,
+        channel_id: recommendation.channel_id,
+    };
+
+    Ok(json(&song))
+}
+
+#[utoipa::path(
+    get, path = "/recommendation/settings", tag = "recommendations",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Recommendation settings of the room", body = RecommendationSettings),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn get_recommendation_settings(db: Database, settings: SharedSettings) -> Result<impl Reply, Rejection> {
+    let settings = settings.read().unwrap().for_room(db.room_name()).clone();
+    Ok(json(&settings))
+}
+
+/// Reloads the settings of every room, answering with those of the room asked.
+#[utoipa::path(
+    post, path = "/recommendation/settings/reload", tag = "recommendations",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Reloaded settings of the room", body = RecommendationSettings),
+        (status = 400, description = "Config file could not be read", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn reload_recommendation_settings(
+    db: Database,
+    settings: SharedSettings,
+    config_file: Option<PathBuf>,
+) -> Result<impl Reply, Rejection> {
+    let reloaded = crate::config::reload_recommendations(config_file.as_deref())
+        .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+    let room_settings = reloaded.for_room(db.room_name()).clone();
+    *settings.write().unwrap() = reloaded;
+    Ok(json(&room_settings))
+}
+
+// Skip vote handlers
+#[utoipa::path(
+    post, path = "/skip-vote", tag = "skip",
+    request_body = SkipVoteRequest,
+    responses(
+        (status = 200, description = "Votes for skipping the current song", body = VoteTally),
+        (status = 409, description = "Nothing is playing", body = ErrorBody),
+    )
+)]
+pub async fn skip_vote(
+    db: Database,
+    skip_votes: SkipVotes,
+    request: SkipVoteRequest,
+) -> Result<impl Reply, Rejection> {
+    let now_playing = playing_now(&db).await?;
+
+    let now_playing_id = now_playing.id.unwrap_or_default();
+    let (tally, outcome) = skip_votes.vote(db.room_name(), now_playing_id, &request.user, db.events().subscriber_count());
+
+    if outcome != VoteOutcome::AlreadySkipped {
+        db.events().publish(QueueEvent::SkipVoteChanged {
+            votes: tally.votes,
+            required: tally.required,
+        });
     }
+    if outcome == VoteOutcome::Skipped {
+        db.events().publish(QueueEvent::SkipRequested { now_playing_id });
+    }
+
+    Ok(json(&tally))
+}
+
+#[utoipa::path(
+    get, path = "/skip-vote", tag = "skip",
+    responses(
+        (status = 200, description = "Votes for skipping the current song", body = VoteTally),
+        (status = 409, description = "Nothing is playing", body = ErrorBody),
+    )
+)]
+pub async fn get_skip_votes(db: Database, skip_votes: SkipVotes) -> Result<impl Reply, Rejection> {
+    let now_playing = playing_now(&db).await?;
+    let tally = skip_votes.tally(db.room_name(), now_playing.id.unwrap_or_default(), db.events().subscriber_count());
+    Ok(json(&tally))
+}
+
+async fn playing_now(db: &Database) -> Result<NowPlaying, ApiError> {
+    db.get_now_playing()
+        .await
+        .map_err(ApiError::internal("Error fetching now playing"))?
+        .ok_or_else(|| ApiError::Conflict("Nothing is playing right now".to_string()))
 }
 
 // Playlist handlers
+#[utoipa::path(
+    post, path = "/playlists", tag = "playlists",
+    request_body = CreatePlaylistRequest,
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 201, description = "Playlist saved", body = PlaylistCreated),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
 pub async fn create_playlist(
     db: Database,
     request: CreatePlaylistRequest,
@@ -202,44 +615,54 @@ pub async fn create_playlist(
         created_at: None,
     };
 
-    match db.create_playlist(&playlist).await {
-        Ok(id) => Ok(with_status(
-            json(&serde_json::json!({
-                "id": id,
-                "message": "Playlist created successfully"
-            })),
-            warp::http::StatusCode::CREATED,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "error": "Error creating playlist"
-            })),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
-    }
+    let id = db.create_playlist(&playlist).await.map_err(ApiError::internal("Error creating playlist"))?;
+    Ok(with_status(
+        json(&PlaylistCreated {
+            id,
+            message: "Playlist created successfully".to_string(),
+        }),
+        warp::http::StatusCode::CREATED,
+    ))
 }
 
+#[utoipa::path(
+    get, path = "/playlists", tag = "playlists",
+    responses((status = 200, description = "Saved playlists", body = Vec<Playlist>))
+)]
 pub async fn get_all_playlists(db: Database) -> Result<impl Reply, Rejection> {
-    match db.get_all_playlists().await {
-        Ok(playlists) => Ok(json(&playlists)),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching playlists"
-        }))),
-    }
+    let playlists = db.get_all_playlists().await.map_err(ApiError::internal("Error fetching playlists"))?;
+    Ok(json(&playlists))
 }
 
+#[utoipa::path(
+    get, path = "/playlists/{id}", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id")),
+    responses(
+        (status = 200, description = "The saved playlist", body = Playlist),
+        (status = 404, description = "No such playlist", body = ErrorBody),
+    )
+)]
 pub async fn get_playlist(db: Database, id: i64) -> Result<impl Reply, Rejection> {
-    match db.get_playlist(id).await {
-        Ok(Some(playlist)) => Ok(json(&playlist)),
-        Ok(None) => Ok(json(&serde_json::json!({
-            "error": "Playlist not found"
-        }))),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching playlist"
-        }))),
-    }
+    Ok(json(&saved_playlist(&db, id).await?))
+}
+
+async fn saved_playlist(db: &Database, id: i64) -> Result<Playlist, ApiError> {
+    db.get_playlist(id)
+        .await
+        .map_err(ApiError::internal("Error fetching playlist"))?
+        .ok_or_else(|| ApiError::NotFound("Playlist not found".to_string()))
 }
 
+#[utoipa::path(
+    post, path = "/playlists/{id}/items", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id")),
+    request_body(content = AddToPlaylistRequest, description = "`playlist_id` is taken from the path"),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 201, description = "Song added", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
 pub async fn add_to_playlist(
     db: Database,
     request: AddToPlaylistRequest,
@@ -253,103 +676,633 @@ pub async fn add_to_playlist(
         created_at: None,
     };
 
-    match db.add_to_playlist(&item).await {
-        Ok(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "message": "Song added to playlist successfully"
-            })),
-            warp::http::StatusCode::CREATED,
-        )),
-        Err(_) => Ok(warp::reply::with_status(
-            json(&serde_json::json!({
-                "error": "Error adding song to playlist"
-            })),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
-    }
+    db.add_to_playlist(&item).await.map_err(ApiError::internal("Error adding song to playlist"))?;
+    Ok(with_status(
+        json(&MessageResponse::new("Song added to playlist successfully")),
+        warp::http::StatusCode::CREATED,
+    ))
 }
 
-pub async fn get_playlist_items(db: Database, id: i64) -> Result<impl Reply, Rejection> {
-    match db.get_playlist_items(id).await {
-        Ok(items) => Ok(json(&items)),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching playlist items"
-        }))),
-    }
+#[utoipa::path(
+    get, path = "/playlists/{id}/items", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id"), ListQuery),
+    responses((status = 200, description = "Songs added to the playlist by hand, in order of adding unless sorted otherwise", body = Vec<PlaylistItem>,
+        headers(("x-total-count" = i64, description = "Songs matching the filters"))))
+)]
+pub async fn get_playlist_items(db: Database, id: i64, query: ListQuery) -> Result<impl Reply, Rejection> {
+    let (items, total) = db
+        .get_playlist_items_page(id, &list_query(query))
+        .await
+        .map_err(ApiError::internal("Error fetching playlist items"))?;
+    Ok(page_reply(&items, total))
 }
 
+#[utoipa::path(
+    get, path = "/playlists/{id}/random", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id")),
+    responses(
+        (status = 200, description = "A random song of the playlist", body = PlaylistItem),
+        (status = 404, description = "The playlist has no songs", body = ErrorBody),
+    )
+)]
 pub async fn get_random_playlist_item(db: Database, id: i64) -> Result<impl Reply, Rejection> {
-    match db.get_random_playlist_item(id).await {
-        Ok(Some(item)) => Ok(json(&item)),
-        Ok(None) => Ok(json(&serde_json::json!({
-            "error": "No items in playlist"
-        }))),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching playlist item"
-        }))),
-    }
+    let item = db
+        .get_random_playlist_item(id)
+        .await
+        .map_err(ApiError::internal("Error fetching playlist item"))?
+        .ok_or_else(|| ApiError::NotFound("No items in playlist".to_string()))?;
+    Ok(json(&item))
 }
 
-#[derive(Debug)]
-pub enum Error {
-    InvalidUrl,
-    YouTubeSearchFailed,
+// Host authentication handlers
+#[utoipa::path(
+    post, path = "/login", tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Host session started, also set as the `sr_session` cookie", body = LoginResponse),
+        (status = 401, description = "Wrong password", body = ErrorBody),
+    )
+)]
+pub async fn login(auth: HostAuth, request: LoginRequest) -> Result<impl Reply, Rejection> {
+    match auth.login(&request.password) {
+        Some(token) => Ok(warp::reply::with_header(
+            with_status(
+                json(&LoginResponse {
+                    token: token.clone(),
+                    message: "Logged in as host".to_string(),
+                }),
+                warp::http::StatusCode::OK,
+            ),
+            "set-cookie",
+            HostAuth::session_cookie(&token),
+        )),
+        None => Err(ApiError::Unauthorized.into()),
+    }
 }
 
-impl warp::reject::Reject for Error {}
+#[utoipa::path(
+    post, path = "/logout", tag = "auth",
+    responses((status = 200, description = "Host session ended", body = MessageResponse))
+)]
+pub async fn logout(auth: HostAuth, token: Option<String>) -> Result<impl Reply, Rejection> {
+    if let Some(token) = token {
+        auth.logout(&token);
+    }
+
+    Ok(warp::reply::with_header(
+        json(&MessageResponse::new("Logged out")),
+        "set-cookie",
+        HostAuth::expired_cookie(),
+    ))
+}
 
 // Playlist queue handlers
+#[utoipa::path(
+    post, path = "/playlist-queue", tag = "playlist-queue",
+    request_body = AddPlaylistSongsRequest,
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 201, description = "Songs of the playlist queued", body = PlaylistSongsAdded),
+        (status = 400, description = "Neither playlist_url nor playlist_id given", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "No such saved playlist", body = ErrorBody),
+        (status = 502, description = "The playlist could not be fetched", body = ErrorBody),
+    )
+)]
 pub async fn add_playlist_songs(
     db: Database,
-    youtube_api: YouTubeAPI,
+    provider: Provider,
     request: AddPlaylistSongsRequest,
 ) -> Result<impl Reply, Rejection> {
-    match youtube_api.get_playlist_songs(&request.playlist_url, &[]).await {
-        Ok(songs) => {
-            let mut added_count = 0;
-            for song in songs {
-                if let Err(e) = db.add_to_playlist_queue(&song.title, &song.url, &song.video_id).await {
-                    eprintln!("Error adding song to playlist queue: {:?}", e);
-                } else {
-                    added_count += 1;
-                }
-            }
-            
-            Ok(warp::reply::with_status(
-                json(&serde_json::json!({
-                    "message": format!("Added {} songs to playlist queue", added_count)
-                })),
-                warp::http::StatusCode::CREATED,
-            ))
+    let saved = match request.playlist_id {
+        Some(id) => Some(saved_playlist(&db, id).await?),
+        None => None,
+    };
+
+    let playlist_url = match (request.playlist_url.filter(|url| !url.trim().is_empty()), &saved) {
+        (Some(url), _) => url,
+        (None, Some(playlist)) => playlist.youtube_playlist_url.clone(),
+        (None, None) => {
+            return Err(ApiError::BadRequest("Either playlist_url or playlist_id is required".to_string()).into())
         }
+    };
+    // A pasted URL of a saved playlist continues where that playlist left off
+    let playlist_id = match saved {
+        Some(playlist) => playlist.id,
+        None => find_saved_playlist(&db, &playlist_url).await,
+    };
+
+    let queued = queue_playlist(&db, &*provider, &playlist_url, playlist_id)
+        .await
+        .map_err(ApiError::upstream("Error fetching playlist songs"))?;
+    let mut message = format!(
+        "Added {} songs to playlist queue, skipped {} blocked",
+        queued.added, queued.blocked
+    );
+    if queued.restarted {
+        message.push_str("; every song had been played, so the playlist starts over");
+    }
+    Ok(with_status(
+        json(&PlaylistSongsAdded { message, playlist_id }),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+struct QueuedPlaylist {
+    added: usize,
+    blocked: usize,
+    // Progress was reset because every song had been played
+    restarted: bool,
+}
+
+/// Adds the songs of a playlist to the playlist queue. For a saved playlist, songs already played
+/// or still queued are skipped, and its progress starts over once every song has been played.
+async fn queue_playlist(
+    db: &Database,
+    provider: &dyn MusicProvider,
+    playlist_url: &str,
+    playlist_id: Option<i64>,
+) -> Result<QueuedPlaylist> {
+    let (played, queued) = match playlist_id {
+        Some(id) => {
+            let queued: HashSet<String> = db.get_all_playlist_songs().await?.into_iter().map(|song| song.video_id).collect();
+            (db.get_played_songs(id).await?, queued)
+        }
+        None => (Vec::new(), HashSet::new()),
+    };
+
+    let mut songs = provider.get_playlist_songs(playlist_url, &played).await?;
+    let mut restarted = false;
+    if let (true, Some(id)) = (songs.is_empty() && !played.is_empty(), playlist_id) {
+        db.reset_playlist_progress(id).await?;
+        songs = provider.get_playlist_songs(playlist_url, &[]).await?;
+        restarted = true;
+    }
+    songs.retain(|song| !queued.contains(&song.video_id));
+
+    let blocklist = Blocklist::load(db).await.unwrap_or_default();
+    let mut queued = QueuedPlaylist { added: 0, blocked: 0, restarted };
+    for song in songs {
+        if !blocklist.allows(&song) {
+            queued.blocked += 1;
+            continue;
+        }
+        if let Err(e) = db.add_to_playlist_queue(&song, playlist_id).await {
+            tracing::warn!(error = ?e, video_id = %song.video_id, "Error adding song to playlist queue");
+        } else {
+            queued.added += 1;
+        }
+    }
+    Ok(queued)
+}
+
+async fn refill_playlist_queue(db: &Database, provider: &dyn MusicProvider, playlist_id: i64) {
+    let playlist = match db.get_playlist(playlist_id).await {
+        Ok(Some(playlist)) => playlist,
+        Ok(None) => return,
         Err(e) => {
-            eprintln!("Error fetching playlist songs: {:?}", e);
-            Ok(warp::reply::with_status(
-                json(&serde_json::json!({
-                    "error": "Error fetching playlist songs"
-                })),
-                warp::http::StatusCode::BAD_REQUEST,
-            ))
+            tracing::error!(error = ?e, playlist_id, "Error fetching playlist");
+            return;
         }
+    };
+    if let Err(e) = queue_playlist(db, provider, &playlist.youtube_playlist_url, Some(playlist_id)).await {
+        tracing::warn!(error = ?e, playlist_id, "Error refilling playlist queue");
     }
 }
 
-pub async fn get_playlist_songs(db: Database) -> Result<impl Reply, Rejection> {
-    match db.get_all_playlist_songs().await {
-        Ok(songs) => Ok(json(&songs)),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error fetching playlist songs"
-        }))),
+async fn find_saved_playlist(db: &Database, playlist_url: &str) -> Option<i64> {
+    let youtube_id = YouTubeAPI::extract_playlist_id(playlist_url)?;
+    db.get_all_playlists()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .find(|playlist| YouTubeAPI::extract_playlist_id(&playlist.youtube_playlist_url).as_ref() == Some(&youtube_id))
+        .and_then(|playlist| playlist.id)
+}
+
+#[utoipa::path(
+    get, path = "/playlists/{id}/progress", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id")),
+    responses(
+        (status = 200, description = "Songs of the playlist played since its progress was reset", body = PlaylistProgressResponse),
+        (status = 404, description = "No such playlist", body = ErrorBody),
+    )
+)]
+pub async fn get_playlist_progress(db: Database, id: i64) -> Result<impl Reply, Rejection> {
+    saved_playlist(&db, id).await?;
+
+    let played = db
+        .get_playlist_progress(id)
+        .await
+        .map_err(ApiError::internal("Error fetching playlist progress"))?;
+    Ok(json(&PlaylistProgressResponse {
+        playlist_id: id,
+        played_count: played.len(),
+        played,
+    }))
+}
+
+#[utoipa::path(
+    delete, path = "/playlists/{id}/progress", tag = "playlists",
+    params(("id" = i64, Path, description = "Saved playlist id")),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Progress reset", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "No such playlist", body = ErrorBody),
+    )
+)]
+pub async fn reset_playlist_progress(db: Database, id: i64) -> Result<impl Reply, Rejection> {
+    saved_playlist(&db, id).await?;
+
+    db.reset_playlist_progress(id)
+        .await
+        .map_err(ApiError::internal("Error resetting playlist progress"))?;
+    Ok(json(&MessageResponse::new("Playlist progress reset")))
+}
+
+#[utoipa::path(
+    get, path = "/playlist-queue", tag = "playlist-queue",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "Songs of the playlist queue, in play order unless sorted otherwise", body = Vec<PlaylistQueueItem>,
+            headers(("x-total-count" = i64, description = "Songs matching the filters"))),
+        (status = 400, description = "Filtered or sorted by user", body = ErrorBody),
+    )
+)]
+pub async fn get_playlist_songs(db: Database, query: ListQuery) -> Result<impl Reply, Rejection> {
+    if query.user.is_some() || query.sort == Some(ListSort::User) {
+        return Err(ApiError::BadRequest("Playlist queue songs have no requester to filter or sort by".to_string()).into());
     }
+
+    let (songs, total) = db
+        .get_playlist_songs_page(&list_query(query))
+        .await
+        .map_err(ApiError::internal("Error fetching playlist songs"))?;
+    Ok(page_reply(&songs, total))
 }
 
+#[utoipa::path(
+    delete, path = "/playlist-queue", tag = "playlist-queue",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Playlist queue cleared", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
 pub async fn clear_playlist_queue(db: Database) -> Result<impl Reply, Rejection> {
-    match db.clear_playlist_queue().await {
-        Ok(_) => Ok(json(&serde_json::json!({
-            "message": "Playlist queue cleared"
-        }))),
-        Err(_) => Ok(json(&serde_json::json!({
-            "error": "Error clearing playlist queue"
-        }))),
+    db.clear_playlist_queue()
+        .await
+        .map_err(ApiError::internal("Error clearing playlist queue"))?;
+    Ok(json(&MessageResponse::new("Playlist queue cleared")))
+}
+
+// Blocklist handlers
+#[utoipa::path(
+    get, path = "/blocklist", tag = "blocklist",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Blocklist entries", body = Vec<BlocklistEntry>),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn get_blocklist(db: Database) -> Result<impl Reply, Rejection> {
+    let entries = db.get_blocklist().await.map_err(ApiError::internal("Error fetching blocklist"))?;
+    Ok(json(&entries))
+}
+
+#[utoipa::path(
+    post, path = "/blocklist", tag = "blocklist",
+    request_body = AddBlockRequest,
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 201, description = "Entry added", body = BlocklistEntry),
+        (status = 400, description = "Empty value", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 409, description = "Already blocked", body = ErrorBody),
+    )
+)]
+pub async fn add_block(db: Database, request: AddBlockRequest) -> Result<impl Reply, Rejection> {
+    let value = Blocklist::normalize(request.kind, &request.value)
+        .ok_or_else(|| ApiError::BadRequest("Blocklist value must not be empty".to_string()))?;
+    let reason = request.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+
+    let entry = db.add_block(request.kind, &value, reason).await.map_err(|e| {
+        // The unique (kind, value) fails for entries already on the list
+        if is_unique_violation(&e) {
+            ApiError::Conflict("Entry already blocked".to_string())
+        } else {
+            ApiError::internal("Error adding blocklist entry")(e)
+        }
+    })?;
+    Ok(with_status(json(&entry), warp::http::StatusCode::CREATED))
+}
+
+#[utoipa::path(
+    delete, path = "/blocklist/{id}", tag = "blocklist",
+    params(("id" = i64, Path, description = "Blocklist entry id")),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Entry removed", body = MessageResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "No such entry", body = ErrorBody),
+    )
+)]
+pub async fn delete_block(db: Database, id: i64) -> Result<impl Reply, Rejection> {
+    if !db.delete_block(id).await.map_err(ApiError::internal("Error removing blocklist entry"))? {
+        return Err(ApiError::NotFound("Blocklist entry not found".to_string()).into());
     }
+    Ok(json(&MessageResponse::new("Blocklist entry removed")))
+}
+
+// Server-sent events
+#[utoipa::path(
+    get, path = "/events", tag = "events",
+    responses((status = 200, description = "Server-sent events, one JSON `QueueEvent` per message", content_type = "text/event-stream", body = QueueEvent))
+)]
+pub async fn events_handler(db: Database) -> Result<impl Reply, Rejection> {
+    let stream = BroadcastStream::new(db.events().subscribe()).map(|event| {
+        let event = event.unwrap_or_else(|BroadcastStreamRecvError::Lagged(missed)| {
+            QueueEvent::Lagged { missed }
+        });
+        warp::sse::Event::default().json_data(&event)
+    });
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+// Search cache handlers
+#[utoipa::path(
+    get, path = "/search-cache", tag = "admin",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Cached YouTube responses", body = SearchCacheResponse),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn get_search_cache(db: Database, cache: SearchCache) -> Result<impl Reply, Rejection> {
+    let entries = db.get_search_cache().await.map_err(ApiError::internal("Error fetching search cache"))?;
+    Ok(json(&SearchCacheResponse {
+        ttl_seconds: cache.ttl().map(|ttl| ttl.as_secs()),
+        entries,
+    }))
+}
+
+#[utoipa::path(
+    delete, path = "/search-cache", tag = "admin",
+    params(("kind" = Option<CacheKind>, Query, description = "Only purge responses of this kind")),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Cached responses purged", body = CachePurged),
+        (status = 400, description = "Unknown kind", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn purge_search_cache(db: Database, kind: Option<String>) -> Result<impl Reply, Rejection> {
+    let kind = match kind.as_deref().map(CacheKind::from_db) {
+        None => None,
+        Some(Some(kind)) => Some(kind),
+        Some(None) => {
+            return Err(ApiError::BadRequest("Unknown cache kind, expected search or playlist_page".to_string()).into())
+        }
+    };
+
+    let purged = db.purge_search_cache(kind).await.map_err(ApiError::internal("Error purging search cache"))?;
+    Ok(json(&CachePurged {
+        message: format!("Purged {} cached responses", purged),
+        purged,
+    }))
+}
+
+#[utoipa::path(
+    get, path = "/admin/quota", tag = "admin",
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "YouTube API quota spent today", body = QuotaUsage),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+    )
+)]
+pub async fn get_quota(quota: QuotaTracker) -> Result<impl Reply, Rejection> {
+    let usage = quota.usage().await.map_err(ApiError::internal("Error fetching API quota usage"))?;
+    Ok(json(&usage))
+}
+
+// Import and export handlers
+#[utoipa::path(
+    get, path = "/export/{target}", tag = "transfer",
+    params(("target" = String, Path, description = "`queue`, `playlist-queue` or `history`"), TransferQuery),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "The songs as a file download", content(
+            (Vec<TransferSong> = "application/json"),
+            (String = "text/csv"),
+            (String = "audio/x-mpegurl")
+        )),
+        (status = 400, description = "Unknown format", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "Unknown target", body = ErrorBody),
+    )
+)]
+pub async fn export_songs(db: Database, target: String, query: TransferQuery) -> Result<warp::reply::Response, Rejection> {
+    let target = TransferTarget::from_path(&target)
+        .ok_or_else(|| ApiError::NotFound(format!("Unknown export target {}", target)))?;
+    let format = match query.format.as_deref().map(TransferFormat::from_name) {
+        None => TransferFormat::Json,
+        Some(Some(format)) => format,
+        Some(None) => return Err(ApiError::BadRequest("Unknown format, expected json, csv or m3u".to_string()).into()),
+    };
+
+    let songs: Result<Vec<TransferSong>> = match target {
+        TransferTarget::Queue => db.get_all_songs().await.map(|songs| {
+            songs
+                .into_iter()
+                .map(|song| TransferSong {
+                    title: song.title,
+                    url: song.url,
+                    user: Some(song.user),
+                    duration_seconds: song.duration_seconds,
+                    played_at: None,
+                })
+                .collect()
+        }),
+        TransferTarget::PlaylistQueue => db.get_all_playlist_songs().await.map(|songs| {
+            songs
+                .into_iter()
+                .map(|song| TransferSong {
+                    title: song.title,
+                    url: song.url,
+                    user: None,
+                    duration_seconds: song.duration_seconds,
+                    played_at: None,
+                })
+                .collect()
+        }),
+        TransferTarget::History => {
+            let limit = query.limit.unwrap_or(500).clamp(1, 5000);
+            // History is stored newest first, a setlist reads oldest first
+            db.get_play_history(limit, 0).await.map(|history| {
+                history
+                    .into_iter()
+                    .rev()
+                    .map(|played| TransferSong {
+                        title: played.title,
+                        url: played.url,
+                        user: Some(played.requester),
+                        duration_seconds: played.duration_seconds,
+                        played_at: played.started_at,
+                    })
+                    .collect()
+            })
+        }
+    };
+
+    let body = songs
+        .and_then(|songs| transfer::export(&songs, format))
+        .map_err(ApiError::internal("Error exporting songs"))?;
+    let reply = warp::reply::with_header(body, "content-type", format.content_type());
+    let reply = warp::reply::with_header(
+        reply,
+        "content-disposition",
+        format!("attachment; filename=\"{}.{}\"", target.as_str(), format.extension()),
+    );
+    Ok(reply.into_response())
+}
+
+/// Adds the songs of an uploaded file to the queue or the playlist queue. Every row is checked
+/// on its own; rows that fail are reported and the others are still imported.
+#[utoipa::path(
+    post, path = "/import/{target}", tag = "transfer",
+    params(("target" = String, Path, description = "`queue` or `playlist-queue`"), TransferQuery),
+    request_body(description = "A file as written by the export, at most 1 MB", content(
+        (Vec<TransferSong> = "application/json"),
+        (String = "text/csv"),
+        (String = "audio/x-mpegurl")
+    )),
+    security(("host_token" = []), ("host_cookie" = [])),
+    responses(
+        (status = 200, description = "Songs imported; rows that failed are listed with their errors", body = ImportResponse),
+        (status = 400, description = "Unreadable file, unknown format, or history as target", body = ErrorBody),
+        (status = 401, description = "No valid host session", body = ErrorBody),
+        (status = 404, description = "Unknown target", body = ErrorBody),
+        (status = 502, description = "YouTube lookup failed", body = ErrorBody),
+    )
+)]
+pub async fn import_songs(
+    db: Database,
+    provider: Provider,
+    target: String,
+    query: TransferQuery,
+    body: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    let target = match TransferTarget::from_path(&target) {
+        Some(TransferTarget::History) => {
+            return Err(ApiError::BadRequest("Play history cannot be imported".to_string()).into())
+        }
+        Some(target) => target,
+        None => return Err(ApiError::NotFound(format!("Unknown import target {}", target)).into()),
+    };
+    let body = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::BadRequest("The file must be UTF-8 text".to_string()))?;
+    let format = match query.format.as_deref().map(TransferFormat::from_name) {
+        None => TransferFormat::detect(body),
+        Some(Some(format)) => format,
+        Some(None) => return Err(ApiError::BadRequest("Unknown format, expected json, csv or m3u".to_string()).into()),
+    };
+    let rows = transfer::parse(body, format)
+        .map_err(|e| ApiError::BadRequest(format!("Could not read the file: {}", e)))?;
+
+    let mut errors = Vec::new();
+    let mut songs = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let row_number = index + 1;
+        let song = match row {
+            Ok(song) => song,
+            Err(error) => {
+                errors.push(ImportError { row: row_number, error });
+                continue;
+            }
+        };
+        match YouTubeAPI::extract_video_id(&song.url) {
+            Some(video_id) => songs.push((row_number, video_id, song)),
+            None => errors.push(ImportError {
+                row: row_number,
+                error: format!("Not a YouTube video URL: {}", song.url),
+            }),
+        }
+    }
+
+    let video_ids: Vec<String> = songs.iter().map(|(_, video_id, _)| video_id.clone()).collect();
+    let videos = if video_ids.is_empty() {
+        Default::default()
+    } else {
+        provider
+            .get_videos(&video_ids)
+            .await
+            .map_err(ApiError::upstream("Could not look up the imported videos"))?
+    };
+    let blocklist = Blocklist::load(&db).await.unwrap_or_else(|e| {
+        tracing::warn!(error = ?e, "Error loading blocklist");
+        Blocklist::default()
+    });
+
+    let mut imported = 0;
+    for (row, video_id, song) in songs {
+        let Some(video) = videos.get(&video_id) else {
+            errors.push(ImportError { row, error: "Video not found or private".to_string() });
+            continue;
+        };
+        if let Err(blocked) = blocklist.check_song(&video_id, video.channel_id.as_deref(), &video.title) {
+            errors.push(ImportError { row, error: blocked.message() });
+            continue;
+        }
+        // Rows without a title get the video's own
+        let title = if song.title.trim().is_empty() { video.title.clone() } else { song.title };
+        // youtu.be and watch?v= links to one video must be the same song for the duplicate check
+        let url = format!("https://www.youtube.com/watch?v={}", video_id);
+
+        let result = match target {
+            TransferTarget::Queue => {
+                let song = YouTubeURL {
+                    id: None,
+                    title,
+                    url,
+                    user: song.user.filter(|user| !user.trim().is_empty()).unwrap_or_else(|| "Import".to_string()),
+                    created_at: None,
+                    duration_seconds: Some(video.duration_seconds),
+                    channel_id: video.channel_id.clone(),
+                };
+                db.add_song(&song).await.map(|_| ()).map_err(|e| {
+                    if is_unique_violation(&e) {
+                        return "Already in the queue".to_string();
+                    }
+                    tracing::warn!(error = ?e, video_id = %video_id, "Error adding song to the queue");
+                    "Error adding song to the queue".to_string()
+                })
+            }
+            _ => {
+                let song = YouTubeSearchResult {
+                    title,
+                    url,
+                    video_id,
+                    duration_seconds: Some(video.duration_seconds),
+                    channel_id: video.channel_id.clone(),
+                };
+                db.add_to_playlist_queue(&song, None).await.map_err(|e| {
+                    tracing::warn!(error = ?e, video_id = %song.video_id, "Error adding song to playlist queue");
+                    "Error adding song to playlist queue".to_string()
+                })
+            }
+        };
+        match result {
+            Ok(()) => imported += 1,
+            Err(error) => errors.push(ImportError { row, error }),
+        }
+    }
+    errors.sort_by_key(|error| error.row);
+
+    Ok(json(&ImportResponse {
+        message: format!("Imported {} songs, {} failed", imported, errors.len()),
+        imported,
+        errors,
+    }))
 }
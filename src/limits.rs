@@ -0,0 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::database::Database;
+use crate::models::YouTubeURL;
+use anyhow::Result;
+
+// Window `searches_per_minute` is counted over
+const SEARCH_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits applied to `POST /url` and `GET /search`. `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct RequestPolicy {
+    pub max_pending_per_user: Option<i64>,
+    pub min_interval: Option<Duration>,
+    pub max_queue_length: Option<i64>,
+    pub song_length: SongLength,
+    // Per client address, as a search costs 100 quota units when it is not cached
+    pub searches_per_minute: Option<i64>,
+}
+
+/// Allowed song durations in seconds, also applied to recommendations. `None` means no bound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SongLength {
+    pub min_seconds: Option<i64>,
+    pub max_seconds: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum LengthRejected {
+    TooLong { seconds: i64, max: i64 },
+    TooShort { seconds: i64, min: i64 },
+}
+
+impl SongLength {
+    pub fn check(&self, seconds: i64) -> Result<(), LengthRejected> {
+        match (self.min_seconds, self.max_seconds) {
+            (_, Some(max)) if seconds > max => Err(LengthRejected::TooLong { seconds, max }),
+            (Some(min), _) if seconds < min => Err(LengthRejected::TooShort { seconds, min }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn allows(&self, seconds: i64) -> bool {
+        self.check(seconds).is_ok()
+    }
+}
+
+impl LengthRejected {
+    pub fn message(&self) -> String {
+        match self {
+            LengthRejected::TooLong { seconds, max } => format!(
+                "That song is {} long, the limit is {}",
+                format_duration(*seconds),
+                format_duration(*max)
+            ),
+            LengthRejected::TooShort { seconds, min } => format!(
+                "That song is only {} long, songs must be at least {}",
+                format_duration(*seconds),
+                format_duration(*min)
+            ),
+        }
+    }
+}
+
+/// Formats seconds as `m:ss`, or `h:mm:ss` from an hour up.
+pub fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[derive(Debug)]
+pub enum LimitExceeded {
+    TooManyPending { max: i64 },
+    Cooldown { retry_after: Duration },
+    QueueFull { max: i64 },
+}
+
+impl LimitExceeded {
+    pub fn message(&self) -> String {
+        match self {
+            LimitExceeded::TooManyPending { max: 1 } => {
+                "You already have a song waiting in the queue. You can request again once it has played".to_string()
+            }
+            LimitExceeded::TooManyPending { max } => format!(
+                "You already have {} songs waiting in the queue. You can request again once one of them has played",
+                max
+            ),
+            LimitExceeded::Cooldown { retry_after } => format!(
+                "You are requesting too fast. You can request again in {} seconds",
+                retry_after.as_secs().max(1)
+            ),
+            LimitExceeded::QueueFull { max } => format!(
+                "The queue is full ({} {}). You can request again once a song has played",
+                max,
+                if *max == 1 { "song" } else { "songs" }
+            ),
+        }
+    }
+
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            LimitExceeded::Cooldown { retry_after } => Some(retry_after.as_secs().max(1)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestLimiter {
+    policy: RequestPolicy,
+    last_request: Arc<Mutex<HashMap<String, Instant>>>,
+    searches: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+}
+
+impl RequestLimiter {
+    pub fn new(policy: RequestPolicy) -> Self {
+        Self {
+            policy,
+            last_request: Arc::new(Mutex::new(HashMap::new())),
+            searches: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Counts a search by `client`, unless it already searched `searches_per_minute` times in the last minute.
+    pub fn search(&self, client: &str) -> Result<(), LimitExceeded> {
+        let Some(max) = self.policy.searches_per_minute else {
+            return Ok(());
+        };
+
+        let mut searches = self.searches.lock().unwrap();
+        searches.retain(|_, times| {
+            times.retain(|at| at.elapsed() < SEARCH_WINDOW);
+            !times.is_empty()
+        });
+        let times = searches.entry(client.to_string()).or_default();
+        if let Some(oldest) = times.front().filter(|_| times.len() as i64 >= max) {
+            return Err(LimitExceeded::Cooldown {
+                retry_after: SEARCH_WINDOW - oldest.elapsed(),
+            });
+        }
+        times.push_back(Instant::now());
+        Ok(())
+    }
+
+    /// Checks every policy for `user` in the room of `db`, returning the first one that is exceeded.
+    pub async fn check(&self, db: &Database, user: &str) -> Result<Option<LimitExceeded>> {
+        if let Some(min_interval) = self.policy.min_interval {
+            let last_request = self.last_request.lock().unwrap().get(&Self::key(db.room_name(), user)).copied();
+            if let Some(elapsed) = last_request.map(|at| at.elapsed()) {
+                if elapsed < min_interval {
+                    return Ok(Some(LimitExceeded::Cooldown {
+                        retry_after: min_interval - elapsed,
+                    }));
+                }
+            }
+        }
+
+        if let Some(max) = self.policy.max_pending_per_user {
+            if db.count_songs_by_user(user).await? >= max {
+                return Ok(Some(LimitExceeded::TooManyPending { max }));
+            }
+        }
+
+        if let Some(max) = self.policy.max_queue_length {
+            if db.count_songs().await? >= max {
+                return Ok(Some(LimitExceeded::QueueFull { max }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Queues `song` for `user` unless the pending or queue length limit was reached since `check`,
+    /// which another request of the same user may have done in the meantime.
+    pub async fn add_song(&self, db: &Database, song: &YouTubeURL) -> Result<Result<i64, LimitExceeded>> {
+        let (max_pending, max_queue) = (self.policy.max_pending_per_user, self.policy.max_queue_length);
+        if let Some(id) = db.add_song_within(song, max_pending, max_queue).await? {
+            return Ok(Ok(id));
+        }
+
+        let exceeded = match (max_pending, max_queue) {
+            (Some(max), Some(_)) if db.count_songs_by_user(&song.user).await? >= max => LimitExceeded::TooManyPending { max },
+            (_, Some(max)) => LimitExceeded::QueueFull { max },
+            (Some(max), None) => LimitExceeded::TooManyPending { max },
+            (None, None) => anyhow::bail!("Song was not queued"),
+        };
+        Ok(Err(exceeded))
+    }
+
+    pub fn song_length(&self) -> SongLength {
+        self.policy.song_length
+    }
+
+    /// Starts the cooldown for `user` in `room` after a successful request.
+    pub fn record(&self, room: &str, user: &str) {
+        let Some(min_interval) = self.policy.min_interval else {
+            return;
+        };
+
+        let mut last_request = self.last_request.lock().unwrap();
+        last_request.retain(|_, at| at.elapsed() < min_interval);
+        last_request.insert(Self::key(room, user), Instant::now());
+    }
+
+    fn key(room: &str, user: &str) -> String {
+        format!("{}:{}", room, user.trim().to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn song_length_bounds_are_inclusive() {
+        let length = SongLength { min_seconds: Some(61), max_seconds: Some(600) };
+        assert!(length.allows(61));
+        assert!(length.allows(600));
+        assert!(matches!(length.check(60), Err(LengthRejected::TooShort { seconds: 60, min: 61 })));
+        assert!(matches!(length.check(601), Err(LengthRejected::TooLong { seconds: 601, max: 600 })));
+
+        let unbounded = SongLength::default();
+        assert!(unbounded.allows(0) && unbounded.allows(36000));
+    }
+
+    #[test]
+    fn length_messages_show_durations() {
+        assert_eq!(format_duration(59), "0:59");
+        assert_eq!(format_duration(3723), "1:02:03");
+        assert_eq!(
+            LengthRejected::TooLong { seconds: 36000, max: 600 }.message(),
+            "That song is 10:00:00 long, the limit is 10:00"
+        );
+        assert_eq!(
+            LengthRejected::TooShort { seconds: 42, min: 61 }.message(),
+            "That song is only 0:42 long, songs must be at least 1:01"
+        );
+    }
+
+    #[test]
+    fn searches_are_limited_per_client() {
+        let limiter = RequestLimiter::new(RequestPolicy { searches_per_minute: Some(2), ..Default::default() });
+        assert!(limiter.search("10.0.0.1").is_ok());
+        assert!(limiter.search("10.0.0.1").is_ok());
+        let Err(LimitExceeded::Cooldown { retry_after }) = limiter.search("10.0.0.1") else {
+            panic!("the third search in a minute was allowed");
+        };
+        assert!(retry_after <= SEARCH_WINDOW && retry_after > Duration::from_secs(50));
+        assert!(limiter.search("10.0.0.2").is_ok());
+
+        let unlimited = RequestLimiter::new(RequestPolicy::default());
+        assert!((0..100).all(|_| unlimited.search("10.0.0.1").is_ok()));
+    }
+
+    #[test]
+    fn limit_messages_count_songs() {
+        assert!(LimitExceeded::TooManyPending { max: 1 }.message().starts_with("You already have a song waiting"));
+        assert!(LimitExceeded::TooManyPending { max: 3 }.message().starts_with("You already have 3 songs waiting"));
+        assert!(LimitExceeded::QueueFull { max: 1 }.message().starts_with("The queue is full (1 song)"));
+        assert!(LimitExceeded::QueueFull { max: 50 }.message().starts_with("The queue is full (50 songs)"));
+        assert_eq!(LimitExceeded::Cooldown { retry_after: Duration::from_millis(200) }.retry_after_secs(), Some(1));
+    }
+}
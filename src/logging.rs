@@ -0,0 +1,113 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use serde::Deserialize;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+// Shorter values would be replaced all over the output
+const MIN_SECRET_LENGTH: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, for log aggregators
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    // A tracing filter such as `info` or `rust_sr=debug,warp=warn`
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+/// Installs the global logger. Every occurrence of one of `secrets` in the output is replaced by
+/// `[REDACTED]`, whatever logged it.
+pub fn init(config: &LoggingConfig, secrets: Vec<String>) {
+    let writer = RedactingWriter::new(secrets);
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.filter))
+        .with_writer(writer);
+
+    match config.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+    }
+}
+
+/// Checks a filter before the logger is installed, which would otherwise ignore what it cannot parse.
+pub fn validate_filter(filter: &str) -> Result<(), String> {
+    EnvFilter::try_new(filter).map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[derive(Clone)]
+struct RedactingWriter {
+    secrets: Arc<[String]>,
+}
+
+impl RedactingWriter {
+    fn new(secrets: Vec<String>) -> Self {
+        RedactingWriter {
+            secrets: secrets.into_iter().filter(|secret| secret.len() >= MIN_SECRET_LENGTH).collect(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RedactingWriter {
+    type Writer = RedactedLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactedLine {
+            secrets: self.secrets.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Collects one log line and writes it to stdout, redacted, once it is complete.
+struct RedactedLine {
+    secrets: Arc<[String]>,
+    buffer: Vec<u8>,
+}
+
+impl Write for RedactedLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RedactedLine {
+    fn drop(&mut self) {
+        let line = redact(&String::from_utf8_lossy(&self.buffer), &self.secrets);
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    }
+}
+
+fn redact(line: &str, secrets: &[String]) -> String {
+    let mut line = line.to_string();
+    for secret in secrets {
+        line = line.replace(secret.as_str(), "[REDACTED]");
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_wherever_they_appear() {
+        let writer = RedactingWriter::new(vec!["AIzaSyFakeKey".to_string(), "hunter2pw".to_string(), "abc".to_string()]);
+        let line = r#"{"message":"GET https://www.googleapis.com/youtube/v3/search?key=AIzaSyFakeKey","password":"hunter2pw","user":"abc"}"#;
+        assert_eq!(
+            redact(line, &writer.secrets),
+            r#"{"message":"GET https://www.googleapis.com/youtube/v3/search?key=[REDACTED]","password":"[REDACTED]","user":"abc"}"#
+        );
+    }
+}
@@ -1,91 +1,334 @@
+// The route chain below is deep enough to exceed the default type recursion limit
+#![recursion_limit = "256"]
+
 mod models;
+mod auth;
+mod blocklist;
+mod cache;
+mod config;
+mod error;
+mod events;
+mod limits;
+mod logging;
+mod quota;
+mod skip;
+mod transfer;
+mod migrations;
 mod database;
+mod provider;
+mod recommend;
 mod youtube;
 mod handlers;
+mod openapi;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "twitch")]
+mod twitch;
 
 use dotenv::dotenv;
-use std::env;
+use utoipa::OpenApi;
 use warp::Filter;
-use crate::database::Database;
-use crate::youtube::YouTubeAPI;
+use crate::auth::{is_host, session_token, with_host_auth, HostAuth};
+use crate::cache::SearchCache;
+use crate::config::Config;
+use crate::database::{Database, DEFAULT_ROOM};
+use crate::error::handle_rejection;
+use crate::provider::Provider;
 use crate::handlers::*;
+use crate::limits::RequestLimiter;
+use crate::quota::QuotaTracker;
+use crate::recommend::SharedSettings;
+use crate::skip::SkipVotes;
 use crate::models::*;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     dotenv().ok();
 
-    let api_key = env::var("YOUTUBE_API_KEY")
-        .expect("YOUTUBE_API_KEY not found in environment");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            // Logging is configured by the config that failed to load
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.logging, config.secrets());
+
+    if let Err(e) = run(config).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(config: Config) -> anyhow::Result<()> {
+    let state = app_state(&config).await?;
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.settings.clone(), config.config_file.clone()));
+
+    #[cfg(feature = "twitch")]
+    if let Some(twitch_config) = config.twitch.clone() {
+        tokio::spawn(twitch::run(twitch_config, state.db.clone(), state.provider.clone(), state.limiter.clone()));
+    }
+
+    let routes = routes(&config, state)?;
+    let address = std::net::SocketAddr::new(config.bind_address, config.port);
+    let (address, server) = warp::serve(routes)
+        .try_bind_ephemeral(address)
+        .map_err(|e| anyhow::anyhow!("Could not listen on {}: {}", address, e))?;
+
+    tracing::info!("Starting server on http://{}/", address);
+    server.await;
+
+    Ok(())
+}
 
-    let database_url = "sqlite:./youtube_urls.db";
-    let db = Database::new(database_url).await?;
-    let youtube_api = YouTubeAPI::new(api_key);
+/// What the routes share: the database and the services built on top of it.
+struct AppState {
+    db: Database,
+    provider: Provider,
+    search_cache: SearchCache,
+    quota: QuotaTracker,
+    limiter: RequestLimiter,
+    settings: SharedSettings,
+    host_auth: HostAuth,
+}
+
+async fn app_state(config: &Config) -> anyhow::Result<AppState> {
+    let db = Database::new(&config.database_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not open database {}: {}", config.database_path.display(), e))?;
+    let search_cache = SearchCache::new(db.clone(), config.search_cache_ttl);
+    let quota = QuotaTracker::new(db.clone(), config.quota);
+    let provider = provider::from_config(
+        &config.provider,
+        config.request_policy.song_length,
+        search_cache.clone(),
+        quota.clone(),
+    )?;
+
+    let host_password = config.host_password.clone().unwrap_or_else(|| {
+        let generated = uuid::Uuid::new_v4().simple().to_string();
+        tracing::warn!(password = %generated, "HOST_PASSWORD not set, generated a host password for this run");
+        generated
+    });
+
+    Ok(AppState {
+        db,
+        provider,
+        search_cache,
+        quota,
+        limiter: RequestLimiter::new(config.request_policy.clone()),
+        settings: std::sync::Arc::new(std::sync::RwLock::new(config.recommendations.clone())),
+        host_auth: HostAuth::new(host_password),
+    })
+}
+
+/// Every route of the server, with JSON errors, CORS and request logging applied.
+fn routes(
+    config: &Config,
+    state: AppState,
+) -> anyhow::Result<impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static> {
+    let AppState { db, provider, search_cache, quota, limiter, settings, host_auth } = state;
+    let host_only = with_host_auth(host_auth.clone());
+    let host_session = is_host(host_auth.clone());
+    let auth_filter = warp::any().map(move || host_auth.clone());
+
+    // Create a filter that clones the database and music provider. Every route also answers under
+    // /rooms/{room}/..., and the database handed to it is scoped to the room in the path.
+    let all_rooms = db.clone();
+    #[cfg(feature = "metrics")]
+    let metrics_db = db.clone();
+    let db_filter = warp::path::full().map(move |path: warp::path::FullPath| db.room(&room_of(path.as_str())));
+    let provider_filter = warp::any().map(move || provider.clone());
+    let limiter_filter = warp::any().map(move || limiter.clone());
+    let search_cache_filter = warp::any().map(move || search_cache.clone());
+    let quota_filter = warp::any().map(move || quota.clone());
+    let settings_filter = warp::any().map(move || settings.clone());
+    let config_file = config.config_file.clone();
+    let config_file_filter = warp::any().map(move || config_file.clone());
+
+    let skip_votes = SkipVotes::new(config.skip_threshold);
+    let skip_votes_filter = warp::any().map(move || skip_votes.clone());
+
+    // Host authentication routes
+    let login_route = warp::path("login")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter.clone())
+        .and(warp::body::json())
+        .and_then(login);
 
-    // Create a filter that clones the database and YouTube API
-    let db_filter = warp::any().map(move || db.clone());
-    let youtube_filter = warp::any().map(move || youtube_api.clone());
+    let logout_route = warp::path("logout")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter.clone())
+        .and(session_token())
+        .and_then(logout);
 
     // Static file serving
+    let static_dir = config.static_dir.clone();
+    let static_dir_filter = warp::any().map(move || static_dir.clone());
     let static_files = warp::path("static")
-        .and(warp::fs::dir("static/"));
+        .and(warp::fs::dir(config.static_dir.clone()));
 
     // Routes
     let requester_route = warp::path::end()
+        .and(static_dir_filter.clone())
         .and_then(requester_handler);
 
     let host_route = warp::path("host")
+        .and(warp::path::end())
+        .and(static_dir_filter.clone())
         .and_then(host_handler);
 
     let add_song_route = warp::path("url")
+        .and(warp::path::end())
         .and(warp::post())
         .and(db_filter.clone())
-        .and(youtube_filter.clone())
+        .and(provider_filter.clone())
+        .and(limiter_filter.clone())
+        .and(host_session.clone())
         .and(warp::body::json())
         .and_then(add_song);
 
+    let search_route = warp::path("search")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and(provider_filter.clone())
+        .and(limiter_filter.clone())
+        .and(quota_filter.clone())
+        .and(host_session.clone())
+        .and(warp::addr::remote())
+        .and(warp::query::<SearchQuery>())
+        .and_then(search_songs);
+
+    let move_song_route = warp::path("url")
+        .and(warp::path::param::<i64>())
+        .and(warp::path("move"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(warp::body::json())
+        .and_then(|id: i64, db: Database, request: MoveSongRequest| move_song(db, id, request.position));
+
+    let bump_song_route = warp::path("url")
+        .and(warp::path::param::<i64>())
+        .and(warp::path("bump"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and_then(|id: i64, db: Database| bump_song(db, id));
+
     let delete_song_route = warp::path("url")
+        .and(warp::path::end())
         .and(warp::delete())
+        .and(host_only.clone())
         .and(db_filter.clone())
         .and(warp::body::json())
         .and_then(delete_song);
 
     let get_oldest_song_route = warp::path("url")
         .and(warp::path("oldest"))
+        .and(warp::path::end())
         .and(warp::get())
+        .and(host_only.clone())
         .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(db_filter.clone())
-        .and(youtube_filter.clone())
-        .and_then(|query: std::collections::HashMap<String, String>, db: Database, youtube_api: YouTubeAPI| {
+        .and(provider_filter.clone())
+        .and(settings_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, db: Database, provider: Provider, settings: SharedSettings| {
             let playlist_id = query.get("playlist_id").and_then(|id| id.parse::<i64>().ok());
-            get_oldest_song(db, youtube_api, playlist_id)
+            get_oldest_song(db, provider, settings, playlist_id)
         });
 
     let get_all_songs_route = warp::path("urls")
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
+        .and(warp::query::<ListQuery>())
         .and_then(get_all_songs);
 
     let get_recommendation_route = warp::path("recommendation")
+        .and(warp::path::end())
         .and(warp::get())
+        .and(host_only.clone())
         .and(db_filter.clone())
-        .and(youtube_filter.clone())
+        .and(provider_filter.clone())
+        .and(settings_filter.clone())
         .and_then(get_recommendation);
 
+    let get_recommendation_settings_route = warp::path("recommendation")
+        .and(warp::path("settings"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(settings_filter.clone())
+        .and_then(get_recommendation_settings);
+
+    let reload_recommendation_settings_route = warp::path("recommendation")
+        .and(warp::path("settings"))
+        .and(warp::path("reload"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(settings_filter.clone())
+        .and(config_file_filter.clone())
+        .and_then(reload_recommendation_settings);
+
+    let get_now_playing_route = warp::path("now-playing")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(get_now_playing);
+
+    let get_history_route = warp::path("history")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and(warp::query::<HistoryQuery>())
+        .and_then(get_history);
+
+    // Skip vote routes
+    let skip_vote_route = warp::path("skip-vote")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(db_filter.clone())
+        .and(skip_votes_filter.clone())
+        .and(warp::body::json())
+        .and_then(skip_vote);
+
+    let get_skip_votes_route = warp::path("skip-vote")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and(skip_votes_filter.clone())
+        .and_then(get_skip_votes);
+
     // Playlist routes
     let create_playlist_route = warp::path("playlists")
+        .and(warp::path::end())
         .and(warp::post())
+        .and(host_only.clone())
         .and(db_filter.clone())
         .and(warp::body::json())
         .and_then(create_playlist);
 
     let get_all_playlists_route = warp::path("playlists")
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
         .and_then(get_all_playlists);
 
     let get_playlist_route = warp::path("playlists")
         .and(warp::path::param::<i64>())
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
         .and_then(|id: i64, db: Database| get_playlist(db, id));
@@ -93,7 +336,9 @@ async fn main() -> anyhow::Result<()> {
     let add_to_playlist_route = warp::path("playlists")
         .and(warp::path::param::<i64>())
         .and(warp::path("items"))
+        .and(warp::path::end())
         .and(warp::post())
+        .and(host_only.clone())
         .and(db_filter.clone())
         .and(warp::body::json())
         .and_then(|id: i64, db: Database, request: AddToPlaylistRequest| {
@@ -105,61 +350,729 @@ async fn main() -> anyhow::Result<()> {
     let get_playlist_items_route = warp::path("playlists")
         .and(warp::path::param::<i64>())
         .and(warp::path("items"))
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
-        .and_then(|id: i64, db: Database| get_playlist_items(db, id));
+        .and(warp::query::<ListQuery>())
+        .and_then(|id: i64, db: Database, query: ListQuery| get_playlist_items(db, id, query));
 
     let get_random_playlist_item_route = warp::path("playlists")
         .and(warp::path::param::<i64>())
         .and(warp::path("random"))
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
         .and_then(|id: i64, db: Database| get_random_playlist_item(db, id));
 
+    let get_playlist_progress_route = warp::path("playlists")
+        .and(warp::path::param::<i64>())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(|id: i64, db: Database| get_playlist_progress(db, id));
+
+    let reset_playlist_progress_route = warp::path("playlists")
+        .and(warp::path::param::<i64>())
+        .and(warp::path("progress"))
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and_then(|id: i64, db: Database| reset_playlist_progress(db, id));
+
     // Playlist queue routes
     let add_playlist_songs_route = warp::path("playlist-queue")
+        .and(warp::path::end())
         .and(warp::post())
+        .and(host_only.clone())
         .and(db_filter.clone())
-        .and(youtube_filter.clone())
+        .and(provider_filter.clone())
         .and(warp::body::json())
         .and_then(add_playlist_songs);
 
     let get_playlist_songs_route = warp::path("playlist-queue")
+        .and(warp::path::end())
         .and(warp::get())
         .and(db_filter.clone())
+        .and(warp::query::<ListQuery>())
         .and_then(get_playlist_songs);
 
     let clear_playlist_queue_route = warp::path("playlist-queue")
+        .and(warp::path::end())
         .and(warp::delete())
+        .and(host_only.clone())
         .and(db_filter.clone())
         .and_then(clear_playlist_queue);
 
-    let routes = requester_route
+    // Blocklist routes
+    let get_blocklist_route = warp::path("blocklist")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and_then(get_blocklist);
+
+    let add_block_route = warp::path("blocklist")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(warp::body::json())
+        .and_then(add_block);
+
+    let delete_block_route = warp::path("blocklist")
+        .and(warp::path::param::<i64>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and_then(|id: i64, db: Database| delete_block(db, id));
+
+    // Search cache routes
+    let get_search_cache_route = warp::path("search-cache")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(search_cache_filter.clone())
+        .and_then(get_search_cache);
+
+    let purge_search_cache_route = warp::path("search-cache")
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and_then(|db: Database, query: std::collections::HashMap<String, String>| {
+            purge_search_cache(db, query.get("kind").cloned())
+        });
+
+    let get_quota_route = warp::path("admin")
+        .and(warp::path("quota"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(host_only.clone())
+        .and(quota_filter.clone())
+        .and_then(get_quota);
+
+    // Import and export routes
+    let export_route = warp::path("export")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(warp::query::<TransferQuery>())
+        .and_then(|target: String, db: Database, query: TransferQuery| export_songs(db, target, query));
+
+    let import_route = warp::path("import")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(host_only.clone())
+        .and(db_filter.clone())
+        .and(provider_filter.clone())
+        .and(warp::query::<TransferQuery>())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::bytes())
+        .and_then(|target: String, db: Database, provider: Provider, query: TransferQuery, body| {
+            import_songs(db, provider, target, query, body)
+        });
+
+    // Room routes
+    let room_prefix = warp::path("rooms")
+        .and(warp::path::param::<String>())
+        .and_then(|room: String| async move {
+            match database::room_name(&room) {
+                Some(_) => Ok(()),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one();
+
+    let get_rooms_route = warp::path("rooms")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || all_rooms.clone()))
+        .and(settings_filter.clone())
+        .and_then(get_rooms);
+
+    // API reference
+    let openapi_document = std::sync::Arc::new(openapi::ApiDoc::openapi().to_pretty_json()?);
+    let openapi_route = warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || openapi_document.clone()))
+        .and_then(openapi::openapi_handler);
+
+    let docs_config = std::sync::Arc::new(utoipa_swagger_ui::Config::from("/openapi.json"));
+    let docs_route = warp::path("docs")
+        .and(warp::get())
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and(warp::any().map(move || docs_config.clone()))
+        .and_then(openapi::docs_handler);
+
+    // Prometheus metrics, for the server as a whole rather than one room
+    #[cfg(feature = "metrics")]
+    let metrics_route = {
+        metrics::init();
+        warp::path("metrics")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::any().map(move || metrics_db.clone()))
+            .and_then(metrics::metrics_handler)
+    };
+
+    // Server-sent events for live queue updates
+    let events_route = warp::path("events")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(db_filter.clone())
+        .and_then(events_handler);
+
+    let cors = warp::cors()
+        .allow_headers(vec!["content-type", "authorization"])
+        .allow_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
+        .expose_headers(vec!["x-total-count"]);
+    let cors = if config.cors_origins.is_empty() {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.cors_origins.iter().map(String::as_str))
+    };
+
+    let api = requester_route
         .or(host_route)
+        .or(login_route)
+        .or(logout_route)
         .or(add_song_route)
+        .or(search_route)
+        .or(move_song_route)
+        .or(bump_song_route)
         .or(delete_song_route)
         .or(get_oldest_song_route)
         .or(get_all_songs_route)
         .or(get_recommendation_route)
+        .or(get_recommendation_settings_route)
+        .or(reload_recommendation_settings_route)
+        .or(get_now_playing_route)
+        .or(get_history_route)
+        .or(skip_vote_route)
+        .or(get_skip_votes_route)
         .or(create_playlist_route)
         .or(get_all_playlists_route)
         .or(get_playlist_route)
         .or(add_to_playlist_route)
         .or(get_playlist_items_route)
         .or(get_random_playlist_item_route)
+        .or(get_playlist_progress_route)
+        .or(reset_playlist_progress_route)
         .or(add_playlist_songs_route)
         .or(get_playlist_songs_route)
         .or(clear_playlist_queue_route)
-        .or(static_files)
-        .with(warp::cors()
-            .allow_any_origin()
-            .allow_headers(vec!["content-type"])
-            .allow_methods(vec!["GET", "POST", "DELETE", "OPTIONS"]));
+        .or(get_blocklist_route)
+        .or(add_block_route)
+        .or(delete_block_route)
+        .or(get_search_cache_route)
+        .or(purge_search_cache_route)
+        .or(get_quota_route)
+        .or(export_route)
+        .or(import_route)
+        .or(events_route)
+        .or(static_files);
 
-    println!("Starting server on http://localhost:420/");
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], 420))
-        .await;
+    let routes = get_rooms_route
+        .or(openapi_route)
+        .or(docs_route)
+        .or(room_prefix.and(api.clone()))
+        .or(api);
+    #[cfg(feature = "metrics")]
+    let routes = metrics_route.or(routes);
+    // Every request gets a span with its own id, carried by everything logged while answering it
+    let routes = routes.recover(handle_rejection).with(cors).with(warp::trace(|info| {
+        tracing::info_span!(
+            "request",
+            request_id = %uuid::Uuid::new_v4().simple(),
+            method = %info.method(),
+            path = %info.path(),
+        )
+    }));
+    #[cfg(feature = "metrics")]
+    let routes = routes.with(warp::log::custom(|info| {
+        metrics::http_request(info.method().as_str(), info.path(), info.status().as_u16(), info.elapsed())
+    }));
 
-    Ok(())
+    Ok(routes)
+}
+
+/// The room named by a `/rooms/{room}/...` path, or the default room for any other path.
+fn room_of(path: &str) -> String {
+    path.strip_prefix("/rooms/")
+        .and_then(|rest| rest.split('/').next())
+        .and_then(database::room_name)
+        .unwrap_or_else(|| DEFAULT_ROOM.to_string())
+}
+
+/// Reloads the recommendation settings from the config file whenever the process gets SIGHUP.
+#[cfg(unix)]
+async fn reload_on_sighup(settings: SharedSettings, config_file: Option<std::path::PathBuf>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!(error = %e, "Could not listen for SIGHUP, recommendation settings will not reload");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match config::reload_recommendations(config_file.as_deref()) {
+            Ok(reloaded) => {
+                *settings.write().unwrap() = reloaded;
+                tracing::info!("Reloaded recommendation settings");
+            }
+            Err(e) => tracing::error!(error = %format!("{:#}", e), "Could not reload recommendation settings"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+    use crate::database::tests::TempDatabase;
+    use super::*;
+
+    const HOST_PASSWORD: &str = "test-host-password";
+
+    /// The full server on the fixture provider, without a network or a config file.
+    async fn app(
+        database: &TempDatabase,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + 'static {
+        app_with(database, &[], &[]).await
+    }
+
+    /// Like `app`, with more command line flags and environment variables.
+    async fn app_with(
+        database: &TempDatabase,
+        flags: &[&str],
+        env: &[(&str, &str)],
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + 'static {
+        let database_path = database.0.to_str().unwrap();
+        let mut args = vec![
+            "--config",
+            "/dev/null",
+            "--provider",
+            "fixture",
+            "--database-path",
+            database_path,
+            "--host-password",
+            HOST_PASSWORD,
+        ];
+        args.extend_from_slice(flags);
+        let config = Config::from_args(&args, env).unwrap();
+        routes(&config, app_state(&config).await.unwrap()).unwrap()
+    }
+
+    /// Logs in as the host and returns the `Authorization` header value.
+    async fn host_session<F>(app: &F) -> String
+    where
+        F: Filter + Clone + 'static,
+        F::Extract: warp::Reply + Send,
+    {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/login")
+            .json(&json!({"password": HOST_PASSWORD}))
+            .reply(app)
+            .await;
+        format!("Bearer {}", body(&response)["token"].as_str().unwrap())
+    }
+
+    fn body(response: &warp::http::Response<warp::hyper::body::Bytes>) -> Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    fn request_song(path: &str, request: Value) -> warp::test::RequestBuilder {
+        warp::test::request().method("POST").path(path).json(&request)
+    }
+
+    #[tokio::test]
+    async fn title_requests_are_queued_per_room() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+
+        let response = request_song("/url", json!({"user": "ann", "title": "night drive"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = request_song("/url", json!({"user": "bob", "title": "Night Drive"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(body(&response)["code"], "already_queued");
+
+        let response = request_song("/url", json!({"user": "ann", "title": "nothing like this"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(&response)["code"], "no_match");
+
+        // Too short for the default length limit
+        let response = request_song("/url", json!({"user": "ann", "video_id": "fixture0010"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body(&response)["code"], "song_length");
+
+        let response = warp::test::request().path("/urls").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-total-count"], "1");
+        let songs = body(&response);
+        assert_eq!(songs[0]["title"], "Fixture Band - Night Drive");
+        assert_eq!(songs[0]["url"], "https://www.youtube.com/watch?v=fixture0002");
+        assert_eq!(songs[0]["duration_seconds"], 187);
+
+        let response = warp::test::request().path("/rooms/party/urls").reply(&app).await;
+        assert_eq!(response.headers()["x-total-count"], "0");
+
+        let response = warp::test::request().path("/rooms/not%20a%20room/urls").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn the_host_plays_the_queue_with_a_session() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+
+        let response = request_song("/url", json!({"user": "ann", "url": "https://youtu.be/fixture0003"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = warp::test::request().path("/url/oldest").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body(&response)["code"], "unauthorized");
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/login")
+            .json(&json!({"password": "wrong"}))
+            .reply(&app)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/login")
+            .json(&json!({"password": HOST_PASSWORD}))
+            .reply(&app)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = body(&response)["token"].as_str().unwrap().to_string();
+
+        let response = warp::test::request()
+            .path("/url/oldest")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&app)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The requester's URL keeps the video's own title
+        assert_eq!(body(&response)["title"], "The Offline Singers - Static Hearts");
+
+        let response = warp::test::request().path("/now-playing").reply(&app).await;
+        assert_eq!(body(&response)["title"], "The Offline Singers - Static Hearts");
+        assert_eq!(body(&response)["duration_seconds"], 243);
+
+        let response = warp::test::request().path("/urls").reply(&app).await;
+        assert_eq!(response.headers()["x-total-count"], "0");
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_reported_as_conflicts() {
+        let database = TempDatabase::new();
+        let app = app_with(&database, &["--static-dir", "/nonexistent"], &[]).await;
+        let session = host_session(&app).await;
+
+        // A missing page is a server error, not a 200 with an error message
+        let response = warp::test::request().path("/").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body(&response)["code"], "internal_error");
+
+        let block = |value: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/blocklist")
+                .header("authorization", &session)
+                .json(&json!({"kind": "video", "value": value}))
+        };
+        assert_eq!(block("fixture0001").reply(&app).await.status(), StatusCode::CREATED);
+        let response = block("fixture0001").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(body(&response)["code"], "conflict");
+
+        // A broken database is not "already queued"
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", database.0.display())).await.unwrap();
+        sqlx::query("DROP TABLE youtube_urls").execute(&pool).await.unwrap();
+        let response = request_song("/url", json!({"user": "ann", "video_id": "fixture0002"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body(&response)["code"], "internal_error");
+    }
+
+    #[tokio::test]
+    async fn searches_are_limited_per_address_and_by_the_quota() {
+        let database = TempDatabase::new();
+        let app = app_with(&database, &[], &[("SEARCHES_PER_MINUTE", "2")]).await;
+        let search = |q: &str, from: &str| {
+            warp::test::request()
+                .path(&format!("/search?q={}", q))
+                .remote_addr(format!("{}:40000", from).parse().unwrap())
+        };
+
+        let response = search("night%20drive", "10.0.0.1").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(&response)[0]["video_id"], "fixture0002");
+        assert_eq!(search("%20", "10.0.0.1").reply(&app).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(search("static", "10.0.0.1").reply(&app).await.status(), StatusCode::OK);
+
+        let response = search("static", "10.0.0.1").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+        // Other addresses and the host are not held up
+        assert_eq!(search("static", "10.0.0.2").reply(&app).await.status(), StatusCode::OK);
+        let session = host_session(&app).await;
+        let response = search("static", "10.0.0.1").header("authorization", &session).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let database = TempDatabase::new();
+        let app = app_with(&database, &[], &[("YOUTUBE_DAILY_QUOTA", "1000"), ("YOUTUBE_QUOTA_RESERVE", "1000")]).await;
+        let response = search("static", "10.0.0.1").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(&response)["code"], "quota_reserved");
+        // Requests by title still search
+        let response = request_song("/url", json!({"user": "ann", "title": "static hearts"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn every_route_has_a_metrics_label() {
+        let sample = |path: &str| path.replace("{id}", "1").replace("{target}", "queue");
+
+        // The OpenAPI document has every route but the pages and the documents themselves
+        let mut paths: Vec<String> = openapi::ApiDoc::openapi().paths.paths.keys().cloned().collect();
+        paths.extend(["/", "/host", "/openapi.json", "/metrics"].map(String::from));
+        for path in &paths {
+            assert_ne!(metrics::route_of(&sample(path)), "unmatched", "{} has no label", path);
+        }
+
+        // And every label is answered by a route, if only with 405 for the wrong method
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let unknown = warp::test::request().path("/no-such-route").reply(&app).await;
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+        // `/events` streams until the client leaves
+        for route in metrics::ROUTES.iter().filter(|route| **route != "/events") {
+            let response = warp::test::request().path(&sample(route)).reply(&app).await;
+            assert_ne!(response.body(), unknown.body(), "no route answers {}", route);
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_changes_are_streamed_to_the_room() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let (address, server) = warp::serve(app.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut events = reqwest::get(format!("http://{}/events", address)).await.unwrap();
+        assert_eq!(events.headers()["content-type"], "text/event-stream");
+
+        // Only the default room's song reaches its subscribers
+        let response = request_song("/rooms/party/url", json!({"user": "bob", "video_id": "fixture0001"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = request_song("/url", json!({"user": "ann", "video_id": "fixture0002"})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let chunk = events.chunk().await.unwrap().unwrap();
+        let message = std::str::from_utf8(&chunk).unwrap();
+        let data: Value = serde_json::from_str(message.trim().strip_prefix("data:").unwrap()).unwrap();
+        assert_eq!(data["type"], "song_added");
+        assert_eq!(data["song"]["title"], "Fixture Band - Night Drive");
+        assert_eq!(data["song"]["user"], "ann");
+    }
+
+    #[tokio::test]
+    async fn played_songs_go_into_the_history() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let session = host_session(&app).await;
+
+        let response = warp::test::request().path("/now-playing").reply(&app).await;
+        assert_eq!(body(&response), Value::Null);
+
+        for (user, video_id) in [("ann", "fixture0001"), ("bob", "fixture0004")] {
+            let response = request_song("/url", json!({"user": user, "video_id": video_id})).reply(&app).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let response = warp::test::request().path("/url/oldest").header("authorization", &session).reply(&app).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = warp::test::request().path("/now-playing").reply(&app).await;
+        let now_playing = body(&response);
+        assert_eq!(now_playing["title"], "The Offline Singers - No Signal");
+        assert_eq!(now_playing["video_id"], "fixture0004");
+        assert_eq!((now_playing["requester"].as_str(), now_playing["source"].as_str()), (Some("bob"), Some("main_queue")));
+
+        // Most recent first
+        let response = warp::test::request().path("/history").reply(&app).await;
+        let history = body(&response);
+        let played: Vec<(&str, i64)> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|song| (song["requester"].as_str().unwrap(), song["duration_seconds"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(played, [("bob", 198), ("ann", 214)]);
+
+        let response = warp::test::request().path("/history?limit=1&offset=1").reply(&app).await;
+        assert_eq!(body(&response)[0]["video_id"], "fixture0001");
+        let response = warp::test::request().path("/rooms/party/history").reply(&app).await;
+        assert_eq!(body(&response), json!([]));
+    }
+
+    #[tokio::test]
+    async fn the_host_reorders_the_queue() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let session = host_session(&app).await;
+        let queue = || async {
+            let response = warp::test::request().path("/urls").reply(&app).await;
+            body(&response)
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|song| (song["id"].as_i64().unwrap(), song["user"].as_str().unwrap().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let users = |queue: &[(i64, String)]| queue.iter().map(|(_, user)| user.clone()).collect::<Vec<_>>();
+
+        for (user, video_id) in [("ann", "fixture0001"), ("bob", "fixture0002"), ("cat", "fixture0003")] {
+            request_song("/url", json!({"user": user, "video_id": video_id})).reply(&app).await;
+        }
+        let ids: Vec<i64> = queue().await.into_iter().map(|(id, _)| id).collect();
+
+        let host_post = |path: String, request: Value| {
+            warp::test::request().method("POST").path(&path).header("authorization", &session).json(&request)
+        };
+        let response = host_post(format!("/url/{}/move", ids[2]), json!({"position": 1})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(users(&queue().await), ["ann", "cat", "bob"]);
+        // Positions past the end move the song last
+        host_post(format!("/url/{}/move", ids[0]), json!({"position": 99})).reply(&app).await;
+        assert_eq!(users(&queue().await), ["cat", "bob", "ann"]);
+        host_post(format!("/url/{}/bump", ids[1]), json!({})).reply(&app).await;
+        assert_eq!(users(&queue().await), ["bob", "cat", "ann"]);
+
+        // Only the host can move songs, and only those of the room
+        let response = warp::test::request().method("POST").path(&format!("/url/{}/bump", ids[0])).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = host_post(format!("/rooms/party/url/{}/bump", ids[0]), json!({})).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let play_next = json!({"user": "dan", "video_id": "fixture0004", "play_next": true});
+        let response = request_song("/url", play_next.clone()).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body(&response)["code"], "host_only");
+        let response = request_song("/url", play_next).header("authorization", &session).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(users(&queue().await), ["dan", "bob", "cat", "ann"]);
+    }
+
+    #[tokio::test]
+    async fn blocked_songs_are_neither_requested_nor_recommended() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let session = host_session(&app).await;
+
+        for (kind, value) in [
+            ("channel", "UCfixtureband"),
+            ("channel", "UCofflinesingers"),
+            ("channel", "UCcachedresponse"),
+            ("keyword", "LOOPBACK"),
+            ("user", "troll"),
+        ] {
+            let response = warp::test::request()
+                .method("POST")
+                .path("/blocklist")
+                .header("authorization", &session)
+                .json(&json!({"kind": kind, "value": value}))
+                .reply(&app)
+                .await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        for request in [
+            json!({"user": "ann", "video_id": "fixture0001"}),
+            json!({"user": "ann", "url": "https://youtu.be/fixture0005"}),
+            json!({"user": "ann", "title": "static hearts"}),
+            json!({"user": " Troll ", "video_id": "fixture0006"}),
+        ] {
+            let response = request_song("/url", request.clone()).reply(&app).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", request);
+            assert_eq!(body(&response)["code"], "blocked");
+        }
+        let response = warp::test::request().path("/urls").reply(&app).await;
+        assert_eq!(response.headers()["x-total-count"], "0");
+
+        // Port 420 is the only song left that is long enough, so it is recommended every time
+        for _ in 0..3 {
+            let response = warp::test::request().path("/recommendation").header("authorization", &session).reply(&app).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(body(&response)["url"], "https://www.youtube.com/watch?v=fixture0006");
+        }
+    }
+
+    #[tokio::test]
+    async fn saved_playlists_continue_where_they_left_off() {
+        let database = TempDatabase::new();
+        let app = app(&database).await;
+        let session = host_session(&app).await;
+        let host = |method: &str, path: &str| {
+            warp::test::request().method(method).path(path).header("authorization", &session)
+        };
+
+        let response = host("POST", "/playlists")
+            .json(&json!({"name": "Fixtures", "youtube_playlist_url": "https://www.youtube.com/playlist?list=PLfixture"}))
+            .reply(&app)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id = body(&response)["id"].as_i64().unwrap();
+        let queue_playlist = || host("POST", "/playlist-queue").json(&json!({"playlist_id": id}));
+        let play = || host("GET", "/url/oldest");
+        let progress = || async {
+            let response = warp::test::request().path(&format!("/playlists/{}/progress", id)).reply(&app).await;
+            body(&response)["played_count"].as_u64().unwrap()
+        };
+
+        let response = queue_playlist().reply(&app).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(body(&response)["message"].as_str().unwrap().starts_with("Added 4 songs"));
+        for _ in 0..2 {
+            play().reply(&app).await;
+        }
+        assert_eq!(progress().await, 2);
+
+        // Queueing it again only adds the songs not played yet
+        host("DELETE", "/playlist-queue").reply(&app).await;
+        let response = queue_playlist().reply(&app).await;
+        assert!(body(&response)["message"].as_str().unwrap().starts_with("Added 2 songs"));
+        let response = play().reply(&app).await;
+        assert_eq!(body(&response)["title"], "Local Host - Loopback Love");
+        play().reply(&app).await;
+        assert_eq!(progress().await, 4);
+
+        // With every song played, the playlist starts over
+        let response = queue_playlist().reply(&app).await;
+        let message = body(&response)["message"].as_str().unwrap().to_string();
+        assert!(message.starts_with("Added 4 songs") && message.contains("starts over"), "{}", message);
+
+        let response = host("DELETE", &format!("/playlists/{}/progress", id)).reply(&app).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(progress().await, 0);
+        let response = warp::test::request().path("/playlists/999/progress").reply(&app).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,234 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use warp::{Rejection, Reply};
+use crate::database::Database;
+use crate::error::ApiError;
+use crate::models::PlaySource;
+use crate::quota::ApiCall;
+
+static REQUESTS_ADDED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("sr_requests_added_total", "Song requests queued").unwrap()
+});
+
+static REQUESTS_REJECTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sr_requests_rejected_total",
+        "Song requests refused, by the error code answered",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+static SONGS_PLAYED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("sr_songs_played_total", "Songs handed out to play, by where they came from", &["source"])
+        .unwrap()
+});
+
+static YOUTUBE_CALLS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("sr_youtube_api_calls_total", "YouTube Data API requests made", &["endpoint"]).unwrap()
+});
+
+static YOUTUBE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sr_youtube_api_failures_total",
+        "YouTube Data API requests that failed or answered with an error status",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+static QUOTA_UNITS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("sr_youtube_quota_units_total", "YouTube API quota units spent", &["endpoint"]).unwrap()
+});
+
+static QUEUE_LENGTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("sr_queue_length", "Songs waiting in each queue of each room", &["room", "queue"]).unwrap()
+});
+
+static HTTP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "sr_http_request_duration_seconds",
+        "Time taken to answer HTTP requests",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+/// Registers every metric up front, so that counters are reported from zero before their first event.
+pub fn init() {
+    LazyLock::force(&REQUESTS_ADDED);
+    LazyLock::force(&REQUESTS_REJECTED);
+    LazyLock::force(&SONGS_PLAYED);
+    LazyLock::force(&YOUTUBE_CALLS);
+    LazyLock::force(&YOUTUBE_FAILURES);
+    LazyLock::force(&QUOTA_UNITS);
+    LazyLock::force(&QUEUE_LENGTH);
+    LazyLock::force(&HTTP_DURATION);
+}
+
+/// Counts the outcome of a song request: queued, or refused with the error code answered.
+pub fn song_requested(rejection: Option<&ApiError>) {
+    match rejection {
+        None => REQUESTS_ADDED.inc(),
+        Some(error) => REQUESTS_REJECTED.with_label_values(&[error.code()]).inc(),
+    }
+}
+
+pub fn song_played(source: PlaySource) {
+    SONGS_PLAYED.with_label_values(&[source.as_str()]).inc();
+}
+
+pub fn youtube_call(call: ApiCall) {
+    YOUTUBE_CALLS.with_label_values(&[call.as_str()]).inc();
+    QUOTA_UNITS.with_label_values(&[call.as_str()]).inc_by(call.units() as u64);
+}
+
+pub fn youtube_failure(call: ApiCall) {
+    YOUTUBE_FAILURES.with_label_values(&[call.as_str()]).inc();
+}
+
+// Paths of the routes in `main`, as reported in the `route` label; a test in `main` checks that
+// the two agree. `{id}` stands for a number, `{target}` for any one segment.
+pub(crate) const ROUTES: &[&str] = &[
+    "/",
+    "/host",
+    "/login",
+    "/logout",
+    "/search",
+    "/url",
+    "/url/oldest",
+    "/url/{id}/move",
+    "/url/{id}/bump",
+    "/urls",
+    "/recommendation",
+    "/recommendation/settings",
+    "/recommendation/settings/reload",
+    "/now-playing",
+    "/history",
+    "/skip-vote",
+    "/playlists",
+    "/playlists/{id}",
+    "/playlists/{id}/items",
+    "/playlists/{id}/random",
+    "/playlists/{id}/progress",
+    "/playlist-queue",
+    "/blocklist",
+    "/blocklist/{id}",
+    "/search-cache",
+    "/admin/quota",
+    "/export/{target}",
+    "/import/{target}",
+    "/events",
+    "/rooms",
+    "/openapi.json",
+    "/metrics",
+];
+
+/// Records how long a request took, under the route that answered it. Paths no route answers
+/// are all recorded as `unmatched`, so clients cannot add label values.
+pub fn http_request(method: &str, path: &str, status: u16, elapsed: Duration) {
+    HTTP_DURATION
+        .with_label_values(&[method, &route_of(path), &status.to_string()])
+        .observe(elapsed.as_secs_f64());
+}
+
+// `/rooms/party/playlists/12/items` becomes `/rooms/{room}/playlists/{id}/items`
+pub(crate) fn route_of(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let (prefix, segments) = match segments.as_slice() {
+        ["rooms", _, rest @ ..] => ("/rooms/{room}", rest),
+        _ => ("", segments.as_slice()),
+    };
+
+    let route = match segments {
+        ["static", _, ..] => Some("/static/{file}"),
+        ["docs", ..] => Some("/docs"),
+        _ => ROUTES.iter().copied().find(|route| matches_route(route, segments)),
+    };
+    match route {
+        Some("/") if !prefix.is_empty() => prefix.to_string(),
+        Some(route) => format!("{}{}", prefix, route),
+        None => "unmatched".to_string(),
+    }
+}
+
+fn matches_route(route: &str, segments: &[&str]) -> bool {
+    let expected: Vec<&str> = route.split('/').filter(|segment| !segment.is_empty()).collect();
+    expected.len() == segments.len()
+        && expected.iter().zip(segments).all(|(expected, segment)| match *expected {
+            "{id}" => segment.parse::<i64>().is_ok(),
+            "{target}" => true,
+            _ => expected == segment,
+        })
+}
+
+/// Serves every metric in the Prometheus text format. Queue lengths are read when scraped.
+pub async fn metrics_handler(db: Database) -> Result<impl Reply, Rejection> {
+    let rooms = db.get_rooms().await.map_err(ApiError::internal("Error fetching queue lengths"))?;
+    QUEUE_LENGTH.reset();
+    for room in rooms {
+        QUEUE_LENGTH.with_label_values(&[room.room.as_str(), "main"]).set(room.queue_length);
+        QUEUE_LENGTH.with_label_values(&[room.room.as_str(), "playlist"]).set(room.playlist_queue_length);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(ApiError::internal("Error encoding metrics"))?;
+    Ok(warp::reply::with_header(buffer, "content-type", encoder.format_type()))
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use super::*;
+
+    #[test]
+    fn routes_keep_ids_and_rooms_out() {
+        assert_eq!(route_of("/"), "/");
+        assert_eq!(route_of("/urls"), "/urls");
+        assert_eq!(route_of("/url/12/move"), "/url/{id}/move");
+        assert_eq!(route_of("/rooms/party/"), "/rooms/{room}");
+        assert_eq!(route_of("/rooms/party/playlists/12/items"), "/rooms/{room}/playlists/{id}/items");
+        assert_eq!(route_of("/export/history"), "/export/{target}");
+        assert_eq!(route_of("/static/app.js"), "/static/{file}");
+        assert_eq!(route_of("/docs/swagger-ui.css"), "/docs");
+    }
+
+    #[test]
+    fn unknown_paths_are_unmatched() {
+        assert_eq!(route_of("/urls/random1"), "unmatched");
+        assert_eq!(route_of("/url/oldest/junk"), "unmatched");
+        assert_eq!(route_of("/playlists/abc"), "unmatched");
+        assert_eq!(route_of("/rooms/party/nope"), "unmatched");
+    }
+
+    #[test]
+    fn junk_paths_share_one_series() {
+        let series = || {
+            HTTP_DURATION
+                .with_label_values(&["GET", "unmatched", "200"])
+                .get_sample_count()
+        };
+        let before = series();
+        for junk in ["/urls/random1", "/urls/random2", "/url/oldest/junk3"] {
+            http_request("GET", junk, 200, Duration::from_millis(1));
+        }
+        assert_eq!(series(), before + 3);
+
+        let routes: Vec<String> = HTTP_DURATION
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .flat_map(|metric| metric.get_label())
+            .filter(|label| label.name() == "route")
+            .map(|label| label.value().to_string())
+            .collect();
+        assert!(routes.iter().all(|route| !route.contains("random") && !route.contains("junk")));
+    }
+}
@@ -0,0 +1,467 @@
+use sqlx::{Row, SqlitePool};
+use anyhow::{bail, Result};
+
+/// A schema change applied once, in order, and recorded in `schema_version`.
+/// Never edit a migration that has shipped; add a new one instead.
+struct Migration {
+    version: i64,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+enum Step {
+    Sql(&'static str),
+    // Databases created before versioning may already have the column
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+        backfill: Option<&'static str>,
+    },
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        steps: &[
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS youtube_urls (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    title TEXT NOT NULL,
+                    url TEXT NOT NULL UNIQUE,
+                    user TEXT NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS playlists (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    description TEXT,
+                    youtube_playlist_url TEXT NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS playlist_items (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    playlist_id INTEGER NOT NULL,
+                    title TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    user TEXT NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS recommended_videos (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    video_id TEXT NOT NULL UNIQUE,
+                    timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS playlist_queue (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    title TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    video_id TEXT NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                CREATE TABLE IF NOT EXISTS playlist_progress (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    playlist_id INTEGER NOT NULL,
+                    video_id TEXT NOT NULL,
+                    title TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    played_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE
+                )
+                "#,
+            ),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_video_id ON recommended_videos(video_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_playlist_id ON playlist_items(playlist_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_playlist_progress_playlist_id ON playlist_progress(playlist_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_playlist_progress_video_id ON playlist_progress(video_id)"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "play history",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS play_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL,
+                video_id TEXT,
+                source TEXT NOT NULL,
+                requester TEXT NOT NULL,
+                started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 3,
+        description: "main queue positions",
+        steps: &[Step::AddColumn {
+            table: "youtube_urls",
+            column: "position",
+            definition: "INTEGER NOT NULL DEFAULT 0",
+            backfill: Some("UPDATE youtube_urls SET position = id"),
+        }],
+    },
+    Migration {
+        version: 4,
+        description: "song durations",
+        steps: &[
+            Step::AddColumn {
+                table: "youtube_urls",
+                column: "duration_seconds",
+                definition: "INTEGER",
+                backfill: None,
+            },
+            Step::AddColumn {
+                table: "playlist_queue",
+                column: "duration_seconds",
+                definition: "INTEGER",
+                backfill: None,
+            },
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "blocklist",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS blocklist (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                value TEXT NOT NULL,
+                reason TEXT,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (kind, value)
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 6,
+        description: "channels for recommendation seeds",
+        steps: &[
+            Step::AddColumn {
+                table: "youtube_urls",
+                column: "channel_id",
+                definition: "TEXT",
+                backfill: None,
+            },
+            Step::AddColumn {
+                table: "play_history",
+                column: "channel_id",
+                definition: "TEXT",
+                backfill: None,
+            },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_play_history_source ON play_history(source)"),
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "search cache",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS search_cache (
+                kind TEXT NOT NULL,
+                cache_key TEXT NOT NULL,
+                response TEXT NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (kind, cache_key)
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 8,
+        description: "api quota usage",
+        steps: &[Step::Sql(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_quota (
+                day TEXT NOT NULL,
+                call TEXT NOT NULL,
+                count INTEGER NOT NULL DEFAULT 0,
+                units INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (day, call)
+            )
+            "#,
+        )],
+    },
+    Migration {
+        version: 9,
+        description: "playlist queue source",
+        steps: &[Step::AddColumn {
+            table: "playlist_queue",
+            column: "playlist_id",
+            definition: "INTEGER",
+            backfill: None,
+        }],
+    },
+    Migration {
+        version: 10,
+        description: "rooms",
+        steps: &[
+            // URLs and recommendations were unique across the whole database, now per room
+            Step::Sql(
+                r#"
+                CREATE TABLE youtube_urls_by_room (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    room TEXT NOT NULL DEFAULT 'default',
+                    title TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    user TEXT NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    position INTEGER NOT NULL DEFAULT 0,
+                    duration_seconds INTEGER,
+                    channel_id TEXT,
+                    UNIQUE (room, url)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO youtube_urls_by_room (id, title, url, user, created_at, position, duration_seconds, channel_id)
+                SELECT id, title, url, user, created_at, position, duration_seconds, channel_id FROM youtube_urls
+                "#,
+            ),
+            Step::Sql("DROP TABLE youtube_urls"),
+            Step::Sql("ALTER TABLE youtube_urls_by_room RENAME TO youtube_urls"),
+            Step::Sql(
+                r#"
+                CREATE TABLE recommended_videos_by_room (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    room TEXT NOT NULL DEFAULT 'default',
+                    video_id TEXT NOT NULL,
+                    timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                    UNIQUE (room, video_id)
+                )
+                "#,
+            ),
+            Step::Sql(
+                r#"
+                INSERT INTO recommended_videos_by_room (id, video_id, timestamp)
+                SELECT id, video_id, timestamp FROM recommended_videos
+                "#,
+            ),
+            Step::Sql("DROP TABLE recommended_videos"),
+            Step::Sql("ALTER TABLE recommended_videos_by_room RENAME TO recommended_videos"),
+            Step::AddColumn {
+                table: "playlist_queue",
+                column: "room",
+                definition: "TEXT NOT NULL DEFAULT 'default'",
+                backfill: None,
+            },
+            Step::AddColumn {
+                table: "playlist_progress",
+                column: "room",
+                definition: "TEXT NOT NULL DEFAULT 'default'",
+                backfill: None,
+            },
+            Step::AddColumn {
+                table: "play_history",
+                column: "room",
+                definition: "TEXT NOT NULL DEFAULT 'default'",
+                backfill: None,
+            },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_youtube_urls_room ON youtube_urls(room, position)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_playlist_queue_room ON playlist_queue(room)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_playlist_progress_room ON playlist_progress(room, playlist_id)"),
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_play_history_room ON play_history(room)"),
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "play history durations",
+        steps: &[Step::AddColumn {
+            table: "play_history",
+            column: "duration_seconds",
+            definition: "INTEGER",
+            backfill: None,
+        }],
+    },
+];
+
+/// Brings the database up to the latest schema version.
+/// Refuses to touch a database written by a newer build.
+pub async fn run(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let current: i64 = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(pool)
+        .await?
+        .get("version");
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+
+    if current > latest {
+        bail!(
+            "Database schema version {} is newer than this build supports (version {}). \
+             Upgrade rust-sr or point it at a different database",
+            current,
+            latest
+        );
+    }
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        let mut tx = pool.begin().await?;
+
+        for step in migration.steps {
+            match step {
+                Step::Sql(sql) => {
+                    sqlx::query(sql).execute(&mut *tx).await?;
+                }
+                Step::AddColumn { table, column, definition, backfill } => {
+                    let exists = sqlx::query("SELECT 1 FROM pragma_table_info(?) WHERE name = ?")
+                        .bind(table)
+                        .bind(column)
+                        .fetch_optional(&mut *tx)
+                        .await?
+                        .is_some();
+                    if exists {
+                        continue;
+                    }
+
+                    sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                        .execute(&mut *tx)
+                        .await?;
+                    if let Some(backfill) = backfill {
+                        sqlx::query(backfill).execute(&mut *tx).await?;
+                    }
+                }
+            }
+        }
+
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        tracing::info!(version = migration.version, "Applied database migration: {}", migration.description);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+    use super::*;
+
+    // The tables as created before the schema was versioned
+    const BASELINE_SCHEMA: &[&str] = &[
+        "CREATE TABLE youtube_urls (id INTEGER PRIMARY KEY AUTOINCREMENT, title TEXT NOT NULL, url TEXT NOT NULL UNIQUE, user TEXT NOT NULL, created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP)",
+        "CREATE TABLE playlists (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, description TEXT, youtube_playlist_url TEXT NOT NULL, created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP)",
+        "CREATE TABLE playlist_items (id INTEGER PRIMARY KEY AUTOINCREMENT, playlist_id INTEGER NOT NULL, title TEXT NOT NULL, url TEXT NOT NULL, user TEXT NOT NULL, created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE)",
+        "CREATE TABLE recommended_videos (id INTEGER PRIMARY KEY AUTOINCREMENT, video_id TEXT NOT NULL UNIQUE, timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP)",
+        "CREATE TABLE playlist_queue (id INTEGER PRIMARY KEY AUTOINCREMENT, title TEXT NOT NULL, url TEXT NOT NULL, video_id TEXT NOT NULL, created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP)",
+        "CREATE TABLE playlist_progress (id INTEGER PRIMARY KEY AUTOINCREMENT, playlist_id INTEGER NOT NULL, video_id TEXT NOT NULL, title TEXT NOT NULL, url TEXT NOT NULL, played_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE)",
+    ];
+
+    // One connection, so every query sees the same in-memory database
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap()
+    }
+
+    async fn version(pool: &SqlitePool) -> i64 {
+        sqlx::query("SELECT MAX(version) AS version FROM schema_version")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get("version")
+    }
+
+    #[tokio::test]
+    async fn upgrades_a_database_from_before_versioning() {
+        let pool = memory_pool().await;
+        for sql in BASELINE_SCHEMA {
+            sqlx::query(sql).execute(&pool).await.unwrap();
+        }
+        for (title, video_id) in [("First", "old0001"), ("Second", "old0002")] {
+            sqlx::query("INSERT INTO youtube_urls (title, url, user) VALUES (?, ?, 'ann')")
+                .bind(title)
+                .bind(format!("https://www.youtube.com/watch?v={}", video_id))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO recommended_videos (video_id) VALUES ('old0003')").execute(&pool).await.unwrap();
+
+        run(&pool).await.unwrap();
+        assert_eq!(version(&pool).await, MIGRATIONS.last().unwrap().version);
+
+        // Queued songs keep their order and land in the default room
+        let rows = sqlx::query("SELECT title, room, position FROM youtube_urls ORDER BY position")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let songs: Vec<(String, String, i64)> =
+            rows.iter().map(|row| (row.get("title"), row.get("room"), row.get("position"))).collect();
+        assert_eq!(
+            songs,
+            [("First".to_string(), "default".to_string(), 1), ("Second".to_string(), "default".to_string(), 2)]
+        );
+        let recommended: String = sqlx::query("SELECT room FROM recommended_videos WHERE video_id = 'old0003'")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get("room");
+        assert_eq!(recommended, "default");
+
+        // Tables and columns added since exist and are usable
+        sqlx::query("INSERT INTO play_history (title, url, source, requester, duration_seconds) VALUES ('First', 'u', 'main_queue', 'ann', 200)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO blocklist (kind, value) VALUES ('user', 'spam')").execute(&pool).await.unwrap();
+
+        // Running again changes nothing
+        run(&pool).await.unwrap();
+        assert_eq!(version(&pool).await, MIGRATIONS.last().unwrap().version);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_newer_schema() {
+        let pool = memory_pool().await;
+        run(&pool).await.unwrap();
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (999, 'from the future')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let error = run(&pool).await.unwrap_err().to_string();
+        assert!(error.contains("newer than this build supports"), "{}", error);
+    }
+}
@@ -1,15 +1,20 @@
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct YouTubeURL {
     pub id: Option<i64>,
     pub title: String,
     pub url: String,
     pub user: String,
     pub created_at: Option<String>,
+    #[serde(default)]
+    pub duration_seconds: Option<i64>,
+    #[serde(default)]
+    pub channel_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Playlist {
     pub id: Option<i64>,
     pub name: String,
@@ -18,7 +23,7 @@ pub struct Playlist {
     pub created_at: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PlaylistItem {
     pub id: Option<i64>,
     pub playlist_id: i64,
@@ -28,6 +33,7 @@ pub struct PlaylistItem {
     pub created_at: Option<String>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecommendedVideo {
     pub id: Option<i64>,
@@ -40,28 +46,81 @@ pub struct YouTubeSearchResult {
     pub title: String,
     pub url: String,
     pub video_id: String,
+    pub duration_seconds: Option<i64>,
+    pub channel_id: Option<String>,
+}
+
+/// A search hit offered to the requester to pick from.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchCandidate {
+    pub video_id: String,
+    pub title: String,
+    pub url: String,
+    pub channel_id: Option<String>,
+    pub channel_title: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub duration_seconds: Option<i64>,
+}
+
+impl From<SearchCandidate> for YouTubeSearchResult {
+    fn from(candidate: SearchCandidate) -> Self {
+        YouTubeSearchResult {
+            title: candidate.title,
+            url: candidate.url,
+            video_id: candidate.video_id,
+            duration_seconds: candidate.duration_seconds,
+            channel_id: candidate.channel_id,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AddSongRequest {
+pub struct VideoDetails {
     pub title: String,
+    pub channel_id: Option<String>,
+    pub duration_seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AddSongRequest {
+    #[serde(default)]
+    pub title: String, // Search query, or the displayed title for a direct URL
     pub user: String,
     pub url: Option<String>, // Optional for direct URL addition
+    #[serde(default)]
+    pub video_id: Option<String>, // A candidate picked from /search
+    #[serde(default)]
+    pub play_next: bool, // Host only: queue ahead of everything else
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// What to search YouTube for
+    #[serde(default)]
+    pub q: String,
+    /// Number of candidates, 5 by default
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MoveSongRequest {
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeleteSongRequest {
     pub url: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreatePlaylistRequest {
     pub name: String,
     pub description: Option<String>,
     pub youtube_playlist_url: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AddToPlaylistRequest {
     pub playlist_id: i64,
     pub title: String,
@@ -69,7 +128,7 @@ pub struct AddToPlaylistRequest {
     pub user: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PlaylistProgress {
     pub id: Option<i64>,
     pub playlist_id: i64,
@@ -79,16 +138,309 @@ pub struct PlaylistProgress {
     pub played_at: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PlaylistQueueItem {
     pub id: Option<i64>,
     pub title: String,
     pub url: String,
     pub video_id: String,
     pub created_at: Option<String>,
+    pub duration_seconds: Option<i64>,
+    // The saved playlist the song was queued from, if any
+    pub playlist_id: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Either a YouTube playlist URL or the id of a saved playlist. Songs of saved playlists
+/// (also when given by a URL matching one) skip what was already played.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AddPlaylistSongsRequest {
-    pub playlist_url: String,
+    #[serde(default)]
+    pub playlist_url: Option<String>,
+    #[serde(default)]
+    pub playlist_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaySource {
+    MainQueue,
+    PlaylistQueue,
+    Recommendation,
+}
+
+impl PlaySource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaySource::MainQueue => "main_queue",
+            PlaySource::PlaylistQueue => "playlist_queue",
+            PlaySource::Recommendation => "recommendation",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "playlist_queue" => PlaySource::PlaylistQueue,
+            "recommendation" => PlaySource::Recommendation,
+            _ => PlaySource::MainQueue,
+        }
+    }
+}
+
+// A row of play_history; the most recent one is what is currently on air
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NowPlaying {
+    pub id: Option<i64>,
+    pub title: String,
+    pub url: String,
+    pub video_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub source: PlaySource,
+    pub requester: String,
+    pub started_at: Option<String>,
+    // Unknown for songs played before durations were kept in the history
+    pub duration_seconds: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Number of songs, 50 by default and at most 500
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ListSort {
+    // Play order for the queues, order of adding for playlist items
+    #[default]
+    Queue,
+    Added,
+    Title,
+    User,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+/// Paging, filters and sorting of `/urls`, `/playlist-queue` and `/playlists/{id}/items`.
+/// The total number of matching songs is sent in the `X-Total-Count` header.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// Number of songs, at most 1000; all of them when not given
+    pub limit: Option<i64>,
+    /// Songs to skip before the first one returned
+    pub offset: Option<i64>,
+    /// Only songs requested by this user, ignoring case; not available on `/playlist-queue`
+    pub user: Option<String>,
+    /// Only songs whose title contains this, ignoring case
+    pub q: Option<String>,
+    /// `queue` (the default), `added`, `title` or `user`; `user` is not available on `/playlist-queue`
+    #[param(inline)]
+    pub sort: Option<ListSort>,
+    /// `asc` (the default) or `desc`
+    #[param(inline)]
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SkipVoteRequest {
+    pub user: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Video,
+    Channel,
+    Keyword,
+    User,
+}
+
+impl BlockKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockKind::Video => "video",
+            BlockKind::Channel => "channel",
+            BlockKind::Keyword => "keyword",
+            BlockKind::User => "user",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "video" => Some(BlockKind::Video),
+            "channel" => Some(BlockKind::Channel),
+            "keyword" => Some(BlockKind::Keyword),
+            "user" => Some(BlockKind::User),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BlocklistEntry {
+    pub id: Option<i64>,
+    pub kind: BlockKind,
+    // Video id, channel id, title keyword or requester name
+    pub value: String,
+    pub reason: Option<String>,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AddBlockRequest {
+    pub kind: BlockKind,
+    pub value: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    Search,
+    PlaylistPage,
+}
+
+impl CacheKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheKind::Search => "search",
+            CacheKind::PlaylistPage => "playlist_page",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "search" => Some(CacheKind::Search),
+            "playlist_page" => Some(CacheKind::PlaylistPage),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CacheEntry {
+    pub kind: CacheKind,
+    // Normalized search query, or `playlist_id:page_token` for playlist pages
+    pub key: String,
+    pub size_bytes: i64,
+    pub created_at: Option<String>,
+}
+
+/// A song as written to and read from export files. Only `url` is required on import.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransferSong {
+    #[serde(default)]
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub duration_seconds: Option<i64>,
+    #[serde(default)]
+    pub played_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TransferQuery {
+    /// `json`, `csv` or `m3u`; exports default to JSON, imports guess from the file
+    pub format: Option<String>,
+    /// Songs of history to export, 500 by default and at most 5000
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImportError {
+    // 1-based position of the song in the uploaded file
+    pub row: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomSummary {
+    pub room: String,
+    pub queue_length: i64,
+    pub playlist_queue_length: i64,
+    pub last_played_at: Option<String>,
+}
+
+// Response bodies of the routes that do not answer with one of the types above
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+impl MessageResponse {
+    pub fn new(message: impl Into<String>) -> Self {
+        MessageResponse { message: message.into() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginResponse {
+    pub token: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlaylistCreated {
+    pub id: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlaylistSongsAdded {
+    pub message: String,
+    // The saved playlist whose progress is tracked, if any
+    pub playlist_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlaylistProgressResponse {
+    pub playlist_id: i64,
+    pub played_count: usize,
+    pub played: Vec<PlaylistProgress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchCacheResponse {
+    // Unset when the cache is disabled
+    pub ttl_seconds: Option<u64>,
+    pub entries: Vec<CacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CachePurged {
+    pub message: String,
+    pub purged: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImportResponse {
+    pub message: String,
+    pub imported: usize,
+    pub errors: Vec<ImportError>,
 }
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+use warp::http::{StatusCode, Uri};
+use warp::path::{FullPath, Tail};
+use warp::{Rejection, Reply};
+use crate::error::ApiError;
+use crate::handlers;
+
+/// The OpenAPI document served at `/openapi.json`, built from the `#[utoipa::path]` attributes
+/// of the handlers and the schemas of the types they take and return.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Rust Song Request Manager",
+        description = "Song request queue for streams. Every path except `/rooms`, `/openapi.json` and \
+            `/docs` also answers under `/rooms/{room}/`, scoped to that room. Failed requests answer \
+            with an `ErrorBody`."
+    ),
+    paths(
+        handlers::login,
+        handlers::logout,
+        handlers::add_song,
+        handlers::search_songs,
+        handlers::move_song,
+        handlers::bump_song,
+        handlers::delete_song,
+        handlers::get_oldest_song,
+        handlers::get_all_songs,
+        handlers::get_now_playing,
+        handlers::get_history,
+        handlers::get_recommendation,
+        handlers::get_recommendation_settings,
+        handlers::reload_recommendation_settings,
+        handlers::skip_vote,
+        handlers::get_skip_votes,
+        handlers::create_playlist,
+        handlers::get_all_playlists,
+        handlers::get_playlist,
+        handlers::add_to_playlist,
+        handlers::get_playlist_items,
+        handlers::get_random_playlist_item,
+        handlers::get_playlist_progress,
+        handlers::reset_playlist_progress,
+        handlers::add_playlist_songs,
+        handlers::get_playlist_songs,
+        handlers::clear_playlist_queue,
+        handlers::get_blocklist,
+        handlers::add_block,
+        handlers::delete_block,
+        handlers::get_search_cache,
+        handlers::purge_search_cache,
+        handlers::get_quota,
+        handlers::export_songs,
+        handlers::import_songs,
+        handlers::get_rooms,
+        handlers::events_handler,
+    ),
+    modifiers(&HostSession),
+    tags(
+        (name = "auth", description = "Host sessions"),
+        (name = "queue", description = "The main request queue"),
+        (name = "playlist-queue", description = "Songs queued from YouTube playlists, played when the main queue is empty"),
+        (name = "playlists", description = "Saved playlists"),
+        (name = "history", description = "What is and was on air"),
+        (name = "recommendations", description = "Songs played when both queues are empty"),
+        (name = "skip", description = "Listener skip votes"),
+        (name = "blocklist", description = "Blocked videos, channels, keywords and requesters"),
+        (name = "transfer", description = "Import and export of queues and history"),
+        (name = "admin", description = "Search cache and YouTube API quota"),
+        (name = "rooms", description = "Rooms with their own queues"),
+        (name = "events", description = "Live queue updates"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Host-only routes take the token from `POST /login` as a bearer token or the session cookie.
+struct HostSession;
+
+impl Modify for HostSession {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "host_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "host_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("sr_session"))),
+        );
+    }
+}
+
+pub async fn openapi_handler(document: Arc<String>) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::with_header(document.to_string(), "content-type", "application/json"))
+}
+
+/// Serves the bundled Swagger UI under `/docs/`, pointed at `/openapi.json`.
+pub async fn docs_handler(full: FullPath, tail: Tail, config: Arc<Config<'static>>) -> Result<warp::reply::Response, Rejection> {
+    // The UI loads its assets relative to the page, so it needs the trailing slash
+    if full.as_str() == "/docs" {
+        return Ok(warp::redirect::found(Uri::from_static("/docs/")).into_response());
+    }
+
+    match utoipa_swagger_ui::serve(tail.as_str(), config) {
+        Ok(Some(file)) => Ok(warp::http::Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", file.content_type)
+            .body(file.bytes.to_vec().into())
+            .unwrap()),
+        Ok(None) => Err(ApiError::NotFound("Not found".to_string()).into()),
+        Err(e) => Err(ApiError::Internal(format!("Error serving API docs: {}", e)).into()),
+    }
+}
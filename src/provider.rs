@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use crate::cache::SearchCache;
+use crate::config::ProviderConfig;
+use crate::limits::SongLength;
+use crate::recommend::RecommendationContext;
+use crate::models::*;
+use crate::quota::QuotaTracker;
+use crate::youtube::YouTubeAPI;
+
+/// Where song searches, recommendations and playlist contents come from.
+#[async_trait]
+pub trait MusicProvider: Send + Sync {
+    /// Searches for songs, best match first. Nothing matching gives an empty list.
+    async fn search_songs(&self, query: &str) -> Result<Vec<SearchCandidate>>;
+
+    /// The best match of a search, or `None` when nothing matches.
+    async fn search_song(&self, query: &str) -> Result<Option<YouTubeSearchResult>> {
+        Ok(self.search_songs(query).await?.into_iter().next().map(YouTubeSearchResult::from))
+    }
+
+    /// Looks up title, channel and duration by video id. Videos that do not exist are left out of the map.
+    async fn get_videos(&self, video_ids: &[String]) -> Result<HashMap<String, VideoDetails>>;
+
+    /// Picks a song to play when both queues are empty, steered by what the room has requested before.
+    /// Avoids the excluded video ids if possible; only songs within the length bounds, not blocked
+    /// and not filtered out by the recommendation settings are picked.
+    async fn get_recommendation(&self, context: &RecommendationContext) -> Result<YouTubeSearchResult>;
+
+    /// Lists the songs of a playlist, leaving out the video ids in `played_songs`.
+    async fn get_playlist_songs(&self, playlist_url: &str, played_songs: &[String]) -> Result<Vec<YouTubeSearchResult>>;
+}
+
+pub type Provider = Arc<dyn MusicProvider>;
+
+// A search costs the same quota however many results it returns
+pub const SEARCH_CANDIDATES: usize = 10;
+
+/// The search cache and quota tracking only apply to YouTube; fixtures cost no quota.
+pub fn from_config(
+    config: &ProviderConfig,
+    song_length: SongLength,
+    cache: SearchCache,
+    quota: QuotaTracker,
+) -> Result<Provider> {
+    Ok(match config {
+        ProviderConfig::YouTube { api_key } => Arc::new(YouTubeAPI::new(api_key.clone(), song_length, cache, quota)),
+        ProviderConfig::Fixture { path } => {
+            let provider = FixtureProvider::load(path.as_deref(), song_length)?;
+            tracing::info!(songs = provider.fixtures.songs.len(), "Using offline fixture provider");
+            Arc::new(provider)
+        }
+    })
+}
+
+// Used when no fixture file is configured
+const DEFAULT_FIXTURES: &str = include_str!("../fixtures/music.json");
+
+#[derive(Debug, Deserialize)]
+struct Fixtures {
+    songs: Vec<FixtureSong>,
+    #[serde(default)]
+    playlists: Vec<FixturePlaylist>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixtureSong {
+    title: String,
+    video_id: String,
+    #[serde(default)]
+    channel_id: Option<String>,
+    #[serde(default)]
+    channel_title: Option<String>,
+    duration_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixturePlaylist {
+    id: String,
+    video_ids: Vec<String>,
+}
+
+/// Offline provider answering from a fixed song list. The same input always gives the same answer.
+pub struct FixtureProvider {
+    fixtures: Fixtures,
+    song_length: SongLength,
+}
+
+impl FixtureProvider {
+    pub fn load(path: Option<&Path>, song_length: SongLength) -> Result<Self> {
+        let fixtures: Fixtures = match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Could not read fixture file {}", path.display()))?;
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("Invalid fixture file {}", path.display()))?
+            }
+            None => serde_json::from_str(DEFAULT_FIXTURES).context("Invalid built-in fixtures")?,
+        };
+
+        if fixtures.songs.is_empty() {
+            anyhow::bail!("Fixture file must contain at least one song");
+        }
+        Ok(Self { fixtures, song_length })
+    }
+
+    fn find(&self, video_id: &str) -> Option<&FixtureSong> {
+        self.fixtures.songs.iter().find(|song| song.video_id == video_id)
+    }
+}
+
+#[async_trait]
+impl MusicProvider for FixtureProvider {
+    async fn search_songs(&self, query: &str) -> Result<Vec<SearchCandidate>> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .fixtures
+            .songs
+            .iter()
+            .filter(|song| {
+                let title = song.title.to_lowercase();
+                words.iter().all(|word| title.contains(word))
+            })
+            .take(SEARCH_CANDIDATES)
+            .map(FixtureSong::to_candidate)
+            .collect())
+    }
+
+    async fn get_videos(&self, video_ids: &[String]) -> Result<HashMap<String, VideoDetails>> {
+        Ok(video_ids
+            .iter()
+            .filter_map(|video_id| self.find(video_id))
+            .map(|song| {
+                let details = VideoDetails {
+                    title: song.title.clone(),
+                    channel_id: song.channel_id.clone(),
+                    duration_seconds: song.duration_seconds,
+                };
+                (song.video_id.clone(), details)
+            })
+            .collect())
+    }
+
+    async fn get_recommendation(&self, context: &RecommendationContext) -> Result<YouTubeSearchResult> {
+        let candidates: Vec<&FixtureSong> = self
+            .fixtures
+            .songs
+            .iter()
+            .filter(|song| self.song_length.allows(song.duration_seconds))
+            .filter(|song| context.blocklist.allows(&song.to_result()))
+            .filter(|song| !context.settings.excludes(&song.title, "", ""))
+            .collect();
+        let first = *candidates.first().context("No fixture song passes the song length limits, blocklist and filters")?;
+
+        // Highest seed score wins, ties go to the earliest song in the file
+        let song = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, song)| !context.excluded.contains(&song.video_id))
+            .max_by_key(|(index, song)| (context.seeds.score(&song.title, song.channel_id.as_deref()), std::cmp::Reverse(*index)))
+            .map(|(_, song)| *song)
+            .unwrap_or(first);
+        Ok(song.to_result())
+    }
+
+    async fn get_playlist_songs(&self, playlist_url: &str, played_songs: &[String]) -> Result<Vec<YouTubeSearchResult>> {
+        let playlist_id = YouTubeAPI::extract_playlist_id(playlist_url)
+            .ok_or_else(|| anyhow::anyhow!("Invalid playlist URL"))?;
+        let playlist = self
+            .fixtures
+            .playlists
+            .iter()
+            .find(|playlist| playlist.id == playlist_id)
+            .with_context(|| format!("Playlist {} not found in fixtures", playlist_id))?;
+
+        Ok(playlist
+            .video_ids
+            .iter()
+            .filter(|video_id| !played_songs.contains(video_id))
+            .filter_map(|video_id| self.find(video_id))
+            .map(FixtureSong::to_result)
+            .collect())
+    }
+}
+
+impl FixtureSong {
+    fn to_candidate(&self) -> SearchCandidate {
+        SearchCandidate {
+            video_id: self.video_id.clone(),
+            title: self.title.clone(),
+            url: format!("https://www.youtube.com/watch?v={}", self.video_id),
+            channel_id: self.channel_id.clone(),
+            channel_title: self.channel_title.clone(),
+            // Offline, so no thumbnails to show
+            thumbnail_url: None,
+            duration_seconds: Some(self.duration_seconds),
+        }
+    }
+
+    fn to_result(&self) -> YouTubeSearchResult {
+        YouTubeSearchResult {
+            title: self.title.clone(),
+            url: format!("https://www.youtube.com/watch?v={}", self.video_id),
+            video_id: self.video_id.clone(),
+            duration_seconds: Some(self.duration_seconds),
+            channel_id: self.channel_id.clone(),
+        }
+    }
+}
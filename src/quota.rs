@@ -0,0 +1,170 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc, Weekday};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::database::Database;
+use anyhow::Result;
+
+/// YouTube Data API calls the server makes, with their documented quota cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiCall {
+    Search,
+    PlaylistItems,
+    Videos,
+}
+
+impl ApiCall {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiCall::Search => "search",
+            ApiCall::PlaylistItems => "playlist_items",
+            ApiCall::Videos => "videos",
+        }
+    }
+
+    pub fn units(&self) -> i64 {
+        match self {
+            ApiCall::Search => 100,
+            ApiCall::PlaylistItems | ApiCall::Videos => 1,
+        }
+    }
+}
+
+/// Why a YouTube call was not made: the rest of today's quota is kept back for song requests.
+#[derive(Debug, thiserror::Error)]
+#[error("The remaining YouTube API quota is kept for song requests")]
+pub struct QuotaReserved;
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaPolicy {
+    pub daily_limit: i64,
+    // Recommendations stop once fewer units than this are left, saving them for requests
+    pub reserve: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuotaUsage {
+    // Quota day in Pacific time, when YouTube resets the quota
+    pub day: String,
+    pub used: i64,
+    pub daily_limit: i64,
+    pub remaining: i64,
+    pub reserve: i64,
+    pub recommendations_enabled: bool,
+    pub calls: Vec<QuotaCalls>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuotaCalls {
+    pub call: String,
+    pub count: i64,
+    pub units: i64,
+}
+
+/// Estimates the YouTube API quota spent today from the calls made, persisted in `api_quota`.
+#[derive(Clone)]
+pub struct QuotaTracker {
+    db: Database,
+    policy: QuotaPolicy,
+}
+
+impl QuotaTracker {
+    pub fn new(db: Database, policy: QuotaPolicy) -> Self {
+        Self { db, policy }
+    }
+
+    /// Counts a call against today's quota. Failures are logged, never passed on.
+    pub async fn record(&self, call: ApiCall) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::youtube_call(call);
+        if let Err(e) = self.db.record_api_call(&quota_day(), call.as_str(), call.units()).await {
+            tracing::warn!(error = ?e, call = call.as_str(), "Error recording API quota usage");
+        }
+    }
+
+    pub async fn usage(&self) -> Result<QuotaUsage> {
+        let day = quota_day();
+        let calls: Vec<QuotaCalls> = self
+            .db
+            .get_api_calls(&day)
+            .await?
+            .into_iter()
+            .map(|(call, count, units)| QuotaCalls { call, count, units })
+            .collect();
+        let used: i64 = calls.iter().map(|calls| calls.units).sum();
+        let remaining = (self.policy.daily_limit - used).max(0);
+
+        Ok(QuotaUsage {
+            day,
+            used,
+            daily_limit: self.policy.daily_limit,
+            remaining,
+            reserve: self.policy.reserve,
+            recommendations_enabled: remaining > self.policy.reserve,
+            calls,
+        })
+    }
+
+    /// Whether more than the reserve is left, which recommendations and `/search` need. Assumes
+    /// there is if usage cannot be read.
+    pub async fn above_reserve(&self) -> bool {
+        match self.usage().await {
+            Ok(usage) => usage.recommendations_enabled,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Error reading API quota usage");
+                true
+            }
+        }
+    }
+}
+
+/// The quota resets at midnight Pacific time.
+fn quota_day() -> String {
+    quota_day_at(Utc::now().naive_utc())
+}
+
+fn quota_day_at(utc: NaiveDateTime) -> String {
+    let offset = if is_pacific_dst(utc) { 7 } else { 8 };
+    (utc - Duration::hours(offset)).date().format("%Y-%m-%d").to_string()
+}
+
+/// US daylight saving time: from 2am on the second Sunday of March to 2am on the first Sunday of November.
+fn is_pacific_dst(utc: NaiveDateTime) -> bool {
+    let year = utc.year();
+    let start = nth_sunday(year, 3, 2).and_hms_opt(10, 0, 0).unwrap(); // 2am PST
+    let end = nth_sunday(year, 11, 1).and_hms_opt(9, 0, 0).unwrap(); // 2am PDT
+    utc >= start && utc < end
+}
+
+fn nth_sunday(year: i32, month: u32, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn daylight_saving_follows_the_us_rules() {
+        // 2024: from March 10 to November 3
+        assert!(!is_pacific_dst(utc("2024-03-10 09:59")));
+        assert!(is_pacific_dst(utc("2024-03-10 10:00")));
+        assert!(is_pacific_dst(utc("2024-07-01 00:00")));
+        assert!(is_pacific_dst(utc("2024-11-03 08:59")));
+        assert!(!is_pacific_dst(utc("2024-11-03 09:00")));
+        assert!(!is_pacific_dst(utc("2024-12-25 12:00")));
+    }
+
+    #[test]
+    fn the_quota_day_ends_at_pacific_midnight() {
+        // PST is UTC-8
+        assert_eq!(quota_day_at(utc("2024-01-15 07:59")), "2024-01-14");
+        assert_eq!(quota_day_at(utc("2024-01-15 08:00")), "2024-01-15");
+        // PDT is UTC-7
+        assert_eq!(quota_day_at(utc("2024-07-15 06:59")), "2024-07-14");
+        assert_eq!(quota_day_at(utc("2024-07-15 07:00")), "2024-07-15");
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::blocklist::Blocklist;
+use crate::database::Database;
+use crate::models::*;
+use crate::provider::MusicProvider;
+use anyhow::Result;
+
+// How far back requests steer recommendations
+const SEED_HISTORY: i64 = 200;
+const SEED_DAYS: i64 = 30;
+const MAX_SEEDS: usize = 10;
+
+/// What the room has been requesting, used to steer recommendations towards the same taste.
+#[derive(Debug, Default)]
+pub struct Seeds {
+    // Most recent first
+    pub artists: Vec<String>,
+    // Most requested first
+    pub songs: Vec<String>,
+    pub channel_ids: Vec<String>,
+}
+
+/// Search and filter rules for recommendations, from the `[recommendations]` config section.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecommendationSettings {
+    // Videos whose title or description contains one of these are skipped (compilations, covers, ...)
+    pub excluded_keywords: Vec<String>,
+    // Also matched against the channel name
+    pub excluded_content_keywords: Vec<String>,
+    // Used while the room has no request history to seed from
+    pub search_queries: Vec<String>,
+    // ISO 639-1 code, empty for no preference
+    pub relevance_language: String,
+    // ISO 3166-1 alpha-2 code, empty for no preference
+    pub region_code: String,
+    // YouTube video category, 10 is Music; empty for any
+    pub video_category_id: String,
+    // Skip videos with a # in the title or description, usually Shorts and promotional uploads
+    pub exclude_hashtags: bool,
+    // Titles over both of these are skipped as likely compilations; 0 turns the check off
+    pub max_title_length: usize,
+    pub max_title_words: usize,
+}
+
+/// Recommendation settings of every room: rooms with a `[rooms.<room>.recommendations]` section
+/// get it merged over `[recommendations]`, the others use `[recommendations]` as is.
+#[derive(Debug, Clone, Default)]
+pub struct RoomSettings {
+    pub default: RecommendationSettings,
+    pub rooms: HashMap<String, RecommendationSettings>,
+}
+
+impl RoomSettings {
+    pub fn for_room(&self, room: &str) -> &RecommendationSettings {
+        self.rooms.get(room).unwrap_or(&self.default)
+    }
+}
+
+/// Settings shared with the request handlers, swapped out when the config file is reloaded.
+pub type SharedSettings = Arc<RwLock<RoomSettings>>;
+
+impl Default for RecommendationSettings {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        Self {
+            excluded_keywords: strings(&[
+                "compilation", "playlist", "mix", "mashup", "megamix",
+                "collection", "best of", "top 10", "top 20", "medley",
+                "hits of", "greatest hits", "complete album",
+                "songs", "tracks", "non stop", "nonstop",
+                "back to back", "b2b", "music collection", "jukeboxes", "jukebox",
+                "all songs", "audio songs", "video songs", "chart",
+                "full album", "album", "live concert", "concert", "live performance",
+                "extended", "remix", "version", "cover", "covers", "acoustic",
+                "karaoke", "instrumental", "piano", "guitar", "drum", "bass",
+                "full", "complete", "entire", "whole",
+            ]),
+            excluded_content_keywords: strings(&[
+                "indian", "hindi", "bollywood", "tamil", "telugu", "punjabi",
+                "bhangra", "desi", "carnatic", "bharatanatyam",
+                "chinese", "mandarin", "cantonese", "japanese", "korean", "thai",
+                "vietnamese", "arabic", "spanish", "french", "german", "italian",
+                "portuguese", "russian", "dutch", "swedish", "norwegian", "danish",
+            ]),
+            // Indonesian and English focus
+            search_queries: strings(&[
+                "popular music", "trending songs", "hit music", "new music", "music video",
+                "song", "music", "artist", "band", "singer",
+                "lagu indonesia", "musik indonesia", "indonesian music", "lagu pop indonesia", "indonesian pop",
+            ]),
+            relevance_language: "id".to_string(),
+            region_code: String::new(),
+            video_category_id: "10".to_string(),
+            exclude_hashtags: true,
+            max_title_length: 70,
+            max_title_words: 10,
+        }
+    }
+}
+
+impl RecommendationSettings {
+    /// Lowercases keywords and drops blank entries so matching can compare lowercased titles directly.
+    pub fn normalized(mut self) -> Self {
+        let clean = |values: Vec<String>, lowercase: bool| {
+            values
+                .into_iter()
+                .map(|value| value.trim().to_string())
+                .map(|value| if lowercase { value.to_lowercase() } else { value })
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        self.excluded_keywords = clean(self.excluded_keywords, true);
+        self.excluded_content_keywords = clean(self.excluded_content_keywords, true);
+        self.search_queries = clean(self.search_queries, false);
+        self.relevance_language = self.relevance_language.trim().to_string();
+        self.region_code = self.region_code.trim().to_uppercase();
+        self.video_category_id = self.video_category_id.trim().to_string();
+        self
+    }
+
+    /// Whether a video looks like a compilation or other content the room does not want, by its
+    /// keywords, hashtags or a very long title.
+    pub fn excludes(&self, title: &str, description: &str, channel_title: &str) -> bool {
+        let (title, description, channel_title) =
+            (title.to_lowercase(), description.to_lowercase(), channel_title.to_lowercase());
+
+        let is_compilation = self
+            .excluded_keywords
+            .iter()
+            .any(|keyword| title.contains(keyword) || description.contains(keyword));
+        let is_excluded_content = self.excluded_content_keywords.iter().any(|keyword| {
+            title.contains(keyword) || channel_title.contains(keyword) || description.contains(keyword)
+        });
+
+        let has_hashtag = self.exclude_hashtags && (title.contains('#') || description.contains('#'));
+        let is_too_long = self.max_title_length > 0
+            && self.max_title_words > 0
+            && title.chars().count() > self.max_title_length
+            && title.split_whitespace().count() > self.max_title_words;
+
+        is_compilation || is_excluded_content || has_hashtag || is_too_long
+    }
+}
+
+/// Everything a provider needs to pick a recommendation.
+pub struct RecommendationContext {
+    pub seeds: Seeds,
+    // Video ids to avoid if there is any other choice
+    pub excluded: Vec<String>,
+    pub blocklist: Blocklist,
+    pub settings: RecommendationSettings,
+}
+
+#[derive(Debug, Clone)]
+pub enum Seed {
+    Artist(String),
+    Song(String),
+    Channel(String),
+}
+
+impl Seeds {
+    /// Builds seeds from songs the audience requested through the main queue.
+    pub async fn load(db: &Database) -> Result<Self> {
+        let mut artists: Vec<String> = Vec::new();
+        for title in db.get_requested_titles(SEED_HISTORY).await? {
+            if let Some(artist) = artist_of(&title) {
+                if !artists.iter().any(|known| known.eq_ignore_ascii_case(&artist)) {
+                    artists.push(artist);
+                }
+            }
+        }
+        artists.truncate(MAX_SEEDS);
+
+        Ok(Self {
+            artists,
+            songs: db.get_most_requested_titles(SEED_DAYS, MAX_SEEDS as i64).await?,
+            channel_ids: db.get_top_requested_channels(SEED_DAYS, MAX_SEEDS as i64).await?,
+        })
+    }
+
+    /// Picks a random seed, or `None` when nothing has been requested yet.
+    pub fn pick(&self) -> Option<Seed> {
+        let mut rng = rand::thread_rng();
+        let mut seeds: Vec<Seed> = Vec::new();
+        seeds.extend(self.artists.choose(&mut rng).cloned().map(Seed::Artist));
+        seeds.extend(self.songs.choose(&mut rng).cloned().map(Seed::Song));
+        seeds.extend(self.channel_ids.choose(&mut rng).cloned().map(Seed::Channel));
+        seeds.choose(&mut rng).cloned()
+    }
+
+    /// Scores how well a song matches the room's taste; 0 means unrelated.
+    pub fn score(&self, title: &str, channel_id: Option<&str>) -> usize {
+        let title = title.to_lowercase();
+        let artist = artist_of(&title);
+
+        let channel = channel_id.is_some_and(|channel_id| self.channel_ids.iter().any(|seed| seed == channel_id));
+        let known_artist = artist.is_some_and(|artist| {
+            self.artists.iter().any(|seed| seed.eq_ignore_ascii_case(&artist))
+        });
+        let liked_artist = self
+            .songs
+            .iter()
+            .filter_map(|song| artist_of(song))
+            .any(|seed| title.contains(&seed.to_lowercase()));
+
+        2 * channel as usize + 2 * known_artist as usize + liked_artist as usize
+    }
+}
+
+/// Gets a recommendation for the room and remembers it so it is not repeated for a while.
+/// Recently recommended and recently played songs are avoided.
+pub async fn recommend(db: &Database, provider: &dyn MusicProvider, settings: &SharedSettings) -> Result<YouTubeSearchResult> {
+    let seeds = Seeds::load(db).await.unwrap_or_else(|e| {
+        tracing::warn!(error = ?e, "Error loading recommendation seeds");
+        Seeds::default()
+    });
+    let mut excluded = db.get_recent_recommendations().await.unwrap_or_default();
+    excluded.extend(db.get_recently_played_video_ids(SEED_HISTORY).await.unwrap_or_default());
+    let context = RecommendationContext {
+        seeds,
+        excluded,
+        blocklist: Blocklist::load(db).await.unwrap_or_default(),
+        settings: settings.read().unwrap().for_room(db.room_name()).clone(),
+    };
+
+    let recommendation = provider.get_recommendation(&context).await?;
+    let _ = db.store_recommendation(&recommendation.video_id).await;
+    Ok(recommendation)
+}
+
+/// Takes the artist from titles in the usual `Artist - Song` form.
+fn artist_of(title: &str) -> Option<String> {
+    let (artist, _) = title.split_once(" - ").or_else(|| title.split_once(" – "))?;
+    let artist = artist.trim();
+    (!artist.is_empty() && artist.len() <= 50).then(|| artist.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashtags_and_long_titles_are_excluded_unless_turned_off() {
+        let settings = RecommendationSettings::default();
+        let long_title = "One Two Three Four Five Six Seven Eight Nine Ten Eleven Twelve Thirteen Fourteen";
+        assert!(!settings.excludes("Fixture Band - Morning Light", "Official video", "Fixture Band"));
+        assert!(settings.excludes("Morning Light #shorts", "", ""));
+        assert!(settings.excludes("Morning Light", "Follow us #fixture", ""));
+        assert!(settings.excludes(long_title, "", ""));
+        // Long, but in few words
+        assert!(!settings.excludes("Supercalifragilisticexpialidocious Antidisestablishmentarianism Floccinaucinihilipilification", "", ""));
+
+        let settings = RecommendationSettings {
+            exclude_hashtags: false,
+            max_title_length: 0,
+            ..Default::default()
+        };
+        assert!(!settings.excludes("Morning Light #shorts", "", ""));
+        assert!(!settings.excludes(long_title, "", ""));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// How many votes it takes to skip the current song.
+#[derive(Debug, Clone, Copy)]
+pub enum SkipThreshold {
+    Absolute(usize),
+    // Fraction of active listeners, between 0 and 1
+    Percent(f64),
+}
+
+impl SkipThreshold {
+    /// Parses `"5"` as an absolute count and `"40%"` as a share of active listeners.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| *percent > 0.0 && *percent <= 100.0)
+                .map(|percent| SkipThreshold::Percent(percent / 100.0)),
+            None => value
+                .parse::<usize>()
+                .ok()
+                .filter(|count| *count > 0)
+                .map(SkipThreshold::Absolute),
+        }
+    }
+
+    pub fn required(&self, listeners: usize) -> usize {
+        match self {
+            SkipThreshold::Absolute(count) => *count,
+            SkipThreshold::Percent(share) => ((listeners as f64 * share).ceil() as usize).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct VoteTally {
+    pub votes: usize,
+    pub required: usize,
+    pub skipped: bool,
+}
+
+/// What a vote did, besides the tally it left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteOutcome {
+    Counted,
+    // This vote reached the threshold
+    Skipped,
+    // The skip already fired for this song, the vote is not counted
+    AlreadySkipped,
+}
+
+#[derive(Default)]
+struct VoteState {
+    // play_history id of the song being voted on
+    now_playing_id: Option<i64>,
+    voters: HashSet<String>,
+    skipped: bool,
+}
+
+/// Skip votes of every room, each voting on its own now-playing song. A room only gets state once
+/// someone votes on a song the host played there, so made-up room names add nothing.
+#[derive(Clone)]
+pub struct SkipVotes {
+    threshold: SkipThreshold,
+    rooms: Arc<Mutex<HashMap<String, VoteState>>>,
+}
+
+impl SkipVotes {
+    pub fn new(threshold: SkipThreshold) -> Self {
+        Self {
+            threshold,
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records a vote from `user` against the song with play_history id `now_playing_id`.
+    /// Votes for a previous song are discarded as soon as the now-playing id changes.
+    pub fn vote(&self, room: &str, now_playing_id: i64, user: &str, listeners: usize) -> (VoteTally, VoteOutcome) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let state = rooms.entry(room.to_string()).or_default();
+        Self::reset_if_changed(state, now_playing_id);
+
+        let required = self.threshold.required(listeners);
+        // Once the skip fired, wait for the host to advance
+        let outcome = if state.skipped {
+            VoteOutcome::AlreadySkipped
+        } else {
+            state.voters.insert(user.trim().to_lowercase());
+            state.skipped = state.voters.len() >= required;
+            if state.skipped { VoteOutcome::Skipped } else { VoteOutcome::Counted }
+        };
+
+        let tally = VoteTally {
+            votes: state.voters.len(),
+            required,
+            skipped: state.skipped,
+        };
+        (tally, outcome)
+    }
+
+    pub fn tally(&self, room: &str, now_playing_id: i64, listeners: usize) -> VoteTally {
+        let rooms = self.rooms.lock().unwrap();
+        // Votes for a previous song no longer count
+        let state = rooms.get(room).filter(|state| state.now_playing_id == Some(now_playing_id));
+
+        VoteTally {
+            votes: state.map_or(0, |state| state.voters.len()),
+            required: self.threshold.required(listeners),
+            skipped: state.is_some_and(|state| state.skipped),
+        }
+    }
+
+    fn reset_if_changed(state: &mut VoteState, now_playing_id: i64) {
+        if state.now_playing_id != Some(now_playing_id) {
+            *state = VoteState {
+                now_playing_id: Some(now_playing_id),
+                ..VoteState::default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_are_counts_or_percentages() {
+        assert!(matches!(SkipThreshold::parse(" 5 "), Some(SkipThreshold::Absolute(5))));
+        assert!(matches!(SkipThreshold::parse("40%"), Some(SkipThreshold::Percent(share)) if share == 0.4));
+        assert!(matches!(SkipThreshold::parse("100 %"), Some(SkipThreshold::Percent(share)) if share == 1.0));
+        for invalid in ["0", "0%", "101%", "-1", "abc", "%", ""] {
+            assert!(SkipThreshold::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn percentages_round_up_and_need_one_vote() {
+        let share = SkipThreshold::parse("40%").unwrap();
+        assert_eq!(share.required(10), 4);
+        assert_eq!(share.required(11), 5);
+        assert_eq!(share.required(0), 1);
+        assert_eq!(SkipThreshold::Absolute(3).required(100), 3);
+    }
+
+    #[test]
+    fn votes_after_the_skip_keep_the_final_tally() {
+        let votes = SkipVotes::new(SkipThreshold::Absolute(2));
+        assert_eq!(votes.vote("default", 1, "ann", 5).1, VoteOutcome::Counted);
+        // Same user, different case: still one vote
+        assert_eq!(votes.vote("default", 1, "ANN", 5).1, VoteOutcome::Counted);
+
+        let (tally, outcome) = votes.vote("default", 1, "bob", 5);
+        assert_eq!(outcome, VoteOutcome::Skipped);
+        assert!(tally.skipped);
+
+        let (tally, outcome) = votes.vote("default", 1, "cat", 5);
+        assert_eq!(outcome, VoteOutcome::AlreadySkipped);
+        assert_eq!((tally.votes, tally.skipped), (2, true));
+    }
+
+    #[test]
+    fn votes_reset_when_the_song_changes() {
+        let votes = SkipVotes::new(SkipThreshold::Absolute(1));
+        assert_eq!(votes.vote("default", 1, "ann", 1).1, VoteOutcome::Skipped);
+        // Other rooms vote on their own song, and looking at the tally keeps nothing for them
+        assert_eq!(votes.tally("party", 1, 1).votes, 0);
+        assert!(!votes.rooms.lock().unwrap().contains_key("party"));
+        assert_eq!((votes.tally("default", 2, 1).votes, votes.tally("default", 2, 1).skipped), (0, false));
+
+        let (tally, outcome) = votes.vote("default", 2, "ann", 1);
+        assert_eq!(outcome, VoteOutcome::Skipped);
+        assert_eq!(tally.votes, 1);
+    }
+}
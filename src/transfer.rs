@@ -0,0 +1,217 @@
+use crate::models::TransferSong;
+use anyhow::{anyhow, Result};
+
+/// File formats queues and history can be exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFormat {
+    Json,
+    Csv,
+    M3u,
+}
+
+impl TransferFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "json" => Some(TransferFormat::Json),
+            "csv" => Some(TransferFormat::Csv),
+            "m3u" | "m3u8" => Some(TransferFormat::M3u),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of an upload that did not name one.
+    pub fn detect(body: &str) -> Self {
+        let body = body.trim_start_matches('\u{feff}').trim_start();
+        if body.starts_with('[') || body.starts_with('{') {
+            TransferFormat::Json
+        } else if body.starts_with("#EXTM3U") {
+            TransferFormat::M3u
+        } else {
+            TransferFormat::Csv
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TransferFormat::Json => "application/json",
+            TransferFormat::Csv => "text/csv; charset=utf-8",
+            TransferFormat::M3u => "audio/x-mpegurl; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TransferFormat::Json => "json",
+            TransferFormat::Csv => "csv",
+            TransferFormat::M3u => "m3u",
+        }
+    }
+}
+
+/// What gets exported or imported: the main queue, the playlist queue or the play history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferTarget {
+    Queue,
+    PlaylistQueue,
+    History,
+}
+
+impl TransferTarget {
+    pub fn from_path(segment: &str) -> Option<Self> {
+        match segment {
+            "queue" => Some(TransferTarget::Queue),
+            "playlist-queue" => Some(TransferTarget::PlaylistQueue),
+            "history" => Some(TransferTarget::History),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferTarget::Queue => "queue",
+            TransferTarget::PlaylistQueue => "playlist-queue",
+            TransferTarget::History => "history",
+        }
+    }
+}
+
+pub fn export(songs: &[TransferSong], format: TransferFormat) -> Result<String> {
+    match format {
+        TransferFormat::Json => Ok(serde_json::to_string_pretty(songs)?),
+        TransferFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for song in songs {
+                writer.serialize(song)?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        TransferFormat::M3u => {
+            let mut playlist = String::from("#EXTM3U\n");
+            for song in songs {
+                // Line breaks would end the #EXTINF line early
+                let title = song.title.replace(['\r', '\n'], " ");
+                playlist.push_str(&format!("#EXTINF:{},{}\n{}\n", song.duration_seconds.unwrap_or(-1), title, song.url));
+            }
+            Ok(playlist)
+        }
+    }
+}
+
+/// Reads the songs of an upload. Fails only when the file as a whole is unreadable;
+/// a row that cannot be read is returned as its error message.
+pub fn parse(body: &str, format: TransferFormat) -> Result<Vec<Result<TransferSong, String>>> {
+    let body = body.trim_start_matches('\u{feff}');
+    match format {
+        TransferFormat::Json => {
+            let rows: Vec<serde_json::Value> = serde_json::from_str(body)
+                .map_err(|e| anyhow!("Expected a JSON array of songs: {}", e))?;
+            Ok(rows
+                .into_iter()
+                .map(|row| serde_json::from_value(row).map_err(|e| e.to_string()))
+                .collect())
+        }
+        TransferFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body.as_bytes());
+            let headers = reader.headers()?.clone();
+            if !headers.iter().any(|header| header == "url") {
+                return Err(anyhow!("The CSV header must have a url column"));
+            }
+            Ok(reader
+                .records()
+                .map(|record| {
+                    record
+                        .and_then(|record| record.deserialize(Some(&headers)))
+                        .map_err(|e| e.to_string())
+                })
+                .collect())
+        }
+        TransferFormat::M3u => Ok(parse_m3u(body)),
+    }
+}
+
+fn parse_m3u(body: &str) -> Vec<Result<TransferSong, String>> {
+    let mut songs = Vec::new();
+    let mut info: Option<(Option<i64>, String)> = None;
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>,<title>
+            let (duration, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let duration = duration.trim().parse::<i64>().ok().filter(|seconds| *seconds >= 0);
+            info = Some((duration, title.trim().to_string()));
+        } else if !line.starts_with('#') {
+            let (duration_seconds, title) = info.take().unwrap_or_default();
+            songs.push(Ok(TransferSong {
+                title,
+                url: line.to_string(),
+                user: None,
+                duration_seconds,
+                played_at: None,
+            }));
+        }
+    }
+    songs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, url: &str, duration_seconds: Option<i64>) -> TransferSong {
+        TransferSong {
+            title: title.to_string(),
+            url: url.to_string(),
+            user: None,
+            duration_seconds,
+            played_at: None,
+        }
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(TransferFormat::detect("\u{feff}[{}]"), TransferFormat::Json);
+        assert_eq!(TransferFormat::detect("  #EXTM3U\n"), TransferFormat::M3u);
+        assert_eq!(TransferFormat::detect("title,url\n"), TransferFormat::Csv);
+        assert_eq!(TransferFormat::from_name("M3U8"), Some(TransferFormat::M3u));
+        assert_eq!(TransferFormat::from_name("xml"), None);
+    }
+
+    #[test]
+    fn m3u_reads_durations_and_titles() {
+        let body = "#EXTM3U\n#EXTINF:214,Fixture Band - Morning Light\nhttps://youtu.be/fixture0001\n\n\
+                    #EXTINF:-1,Unknown length\nhttps://www.youtube.com/watch?v=fixture0002\n\
+                    https://www.youtube.com/watch?v=fixture0003\n";
+        let songs: Vec<TransferSong> = parse_m3u(body).into_iter().map(Result::unwrap).collect();
+        assert_eq!(songs.len(), 3);
+        assert_eq!(songs[0].title, "Fixture Band - Morning Light");
+        assert_eq!(songs[0].duration_seconds, Some(214));
+        assert_eq!(songs[1].duration_seconds, None);
+        // A URL without #EXTINF has no title
+        assert_eq!((songs[2].title.as_str(), songs[2].duration_seconds), ("", None));
+    }
+
+    #[test]
+    fn exports_read_back() {
+        let songs = vec![
+            song("Comma, \"quoted\"", "https://www.youtube.com/watch?v=a", Some(200)),
+            song("Line\nbreak", "https://www.youtube.com/watch?v=b", None),
+        ];
+        for format in [TransferFormat::Json, TransferFormat::Csv, TransferFormat::M3u] {
+            let body = export(&songs, format).unwrap();
+            assert_eq!(TransferFormat::detect(&body), format);
+            let parsed: Vec<TransferSong> = parse(&body, format).unwrap().into_iter().map(Result::unwrap).collect();
+            assert_eq!(parsed.len(), 2, "{:?}", format);
+            assert_eq!(parsed[0].url, "https://www.youtube.com/watch?v=a");
+            assert_eq!(parsed[0].duration_seconds, Some(200));
+        }
+    }
+
+    #[test]
+    fn unreadable_rows_are_reported_one_by_one() {
+        let rows = parse(r#"[{"title": "ok", "url": "https://youtu.be/a"}, {"title": "no url"}]"#, TransferFormat::Json).unwrap();
+        assert!(rows[0].is_ok());
+        assert!(rows[1].is_err());
+
+        assert!(parse("title,link\na,b\n", TransferFormat::Csv).is_err());
+        assert!(parse("{\"not\": \"an array\"}", TransferFormat::Json).is_err());
+    }
+}
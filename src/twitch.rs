@@ -0,0 +1,290 @@
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use crate::database::Database;
+use crate::handlers::request_song;
+use crate::limits::RequestLimiter;
+use crate::models::*;
+use crate::provider::Provider;
+use crate::youtube::YouTubeAPI;
+use anyhow::Result;
+
+// Twitch drops messages longer than 500 characters
+const MAX_REPLY_LEN: usize = 450;
+
+#[derive(Debug, Clone)]
+pub struct TwitchConfig {
+    pub host: String,
+    pub port: u16,
+    // Connect with TLS, which Twitch serves on 6697
+    pub tls: bool,
+    pub nick: String,
+    pub token: Option<String>,
+    pub channel: String,
+    // Room the chat's requests are queued in
+    pub room: String,
+}
+
+#[derive(Debug)]
+enum ChatCommand {
+    SongRequest(String),
+    Queue,
+    Song,
+    WrongSong,
+}
+
+struct PrivMsg {
+    user: String,
+    text: String,
+}
+
+struct Bot {
+    db: Database,
+    provider: Provider,
+    limiter: RequestLimiter,
+}
+
+/// Runs the chat bot forever, reconnecting with a backoff when the connection drops.
+pub async fn run(config: TwitchConfig, db: Database, provider: Provider, limiter: RequestLimiter) {
+    if config.token.is_none() {
+        tracing::warn!(
+            nick = %config.nick,
+            "No Twitch bot token set, the bot logs in read-only: it queues !sr requests but Twitch drops its replies. \
+             Set TWITCH_BOT_NICK and TWITCH_BOT_TOKEN to answer in chat"
+        );
+    }
+    let bot = Bot { db: db.room(&config.room), provider, limiter };
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        match bot.connect(&config).await {
+            Ok(()) => {
+                tracing::warn!("Twitch IRC connection closed, reconnecting");
+                backoff = Duration::from_secs(1);
+            }
+            Err(e) => tracing::error!(error = %e, "Twitch IRC error"),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(60));
+    }
+}
+
+impl Bot {
+    async fn connect(&self, config: &TwitchConfig) -> Result<()> {
+        let stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+        if !config.tls {
+            return self.chat(config, stream).await;
+        }
+
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        let stream = connector.connect(&config.host, stream).await?;
+        self.chat(config, stream).await
+    }
+
+    async fn chat<S: AsyncRead + AsyncWrite>(&self, config: &TwitchConfig, stream: S) -> Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        if let Some(token) = &config.token {
+            send_line(&mut writer, &format!("PASS {}", token)).await?;
+        }
+        send_line(&mut writer, &format!("NICK {}", config.nick)).await?;
+        send_line(&mut writer, &format!("JOIN #{}", config.channel)).await?;
+        tracing::info!(
+            channel = %config.channel,
+            host = %config.host,
+            port = config.port,
+            tls = config.tls,
+            "Twitch bot joined the channel"
+        );
+
+        while let Some(line) = lines.next_line().await? {
+            if let Some(server) = line.strip_prefix("PING") {
+                send_line(&mut writer, &format!("PONG{}", server)).await?;
+                continue;
+            }
+
+            let Some(message) = parse_privmsg(&line) else {
+                continue;
+            };
+            let Some(command) = parse_command(&message.text) else {
+                continue;
+            };
+
+            let reply = self.handle(command, &message.user).await;
+            let reply = truncate(&format!("@{} {}", message.user, reply), MAX_REPLY_LEN);
+            send_line(&mut writer, &format!("PRIVMSG #{} :{}", config.channel, reply)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle(&self, command: ChatCommand, user: &str) -> String {
+        match command {
+            ChatCommand::SongRequest(query) => self.song_request(query, user).await,
+            ChatCommand::Queue => match self.db.get_all_songs().await {
+                Ok(songs) if songs.is_empty() => "The queue is empty".to_string(),
+                Ok(songs) => {
+                    let upcoming: Vec<String> = songs
+                        .iter()
+                        .take(3)
+                        .enumerate()
+                        .map(|(i, song)| format!("{}. {} ({})", i + 1, song.title, song.user))
+                        .collect();
+                    format!("{} songs in queue: {}", songs.len(), upcoming.join(" | "))
+                }
+                Err(_) => "Could not read the queue".to_string(),
+            },
+            ChatCommand::Song => match self.db.get_now_playing().await {
+                Ok(Some(now_playing)) => format!(
+                    "Now playing: {} (requested by {}) {}",
+                    now_playing.title, now_playing.requester, now_playing.url
+                ),
+                Ok(None) => "Nothing is playing right now".to_string(),
+                Err(_) => "Could not read the current song".to_string(),
+            },
+            ChatCommand::WrongSong => match self.db.get_latest_song_by_user(user).await {
+                Ok(Some(song)) => match self.db.delete_song_by_id(song.id.unwrap_or_default()).await {
+                    Ok(true) => format!("Removed your request: {}", song.title),
+                    _ => "Could not remove your request".to_string(),
+                },
+                Ok(None) => "You have no songs in the queue".to_string(),
+                Err(_) => "Could not remove your request".to_string(),
+            },
+        }
+    }
+
+    async fn song_request(&self, query: String, user: &str) -> String {
+        // A URL is not a title: leave it empty so the video's own title is looked up
+        let (title, url) = match YouTubeAPI::extract_video_id(&query) {
+            Some(_) => (String::new(), Some(query)),
+            None => (query, None),
+        };
+        let request = AddSongRequest {
+            title,
+            user: user.to_string(),
+            url,
+            video_id: None,
+            play_next: false,
+        };
+
+        match request_song(&self.db, &*self.provider, &self.limiter, false, request).await {
+            Ok(song) => format!("Added to the queue: {}", song.title),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+/// Writes one IRC line. Replies carry song titles and user names, so any CR, LF or NUL
+/// in them is replaced to keep them from ending the line and sending commands of their own.
+async fn send_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> Result<()> {
+    let line = line.replace(['\r', '\n', '\0'], " ");
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+/// Parses `[@tags] :nick!user@host PRIVMSG #channel :text` into the sender and text.
+fn parse_privmsg(line: &str) -> Option<PrivMsg> {
+    let line = match line.strip_prefix('@') {
+        Some(tagged) => tagged.split_once(' ')?.1,
+        None => line,
+    };
+
+    let (prefix, rest) = line.strip_prefix(':')?.split_once(' ')?;
+    let (command, rest) = rest.split_once(' ')?;
+    if command != "PRIVMSG" {
+        return None;
+    }
+
+    let (_channel, text) = rest.split_once(" :")?;
+    let user = prefix.split('!').next()?.to_string();
+
+    Some(PrivMsg {
+        user,
+        text: text.trim().to_string(),
+    })
+}
+
+fn parse_command(text: &str) -> Option<ChatCommand> {
+    let (name, args) = match text.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (text, ""),
+    };
+
+    match name.to_lowercase().as_str() {
+        "!sr" if !args.is_empty() => Some(ChatCommand::SongRequest(args.to_string())),
+        "!queue" => Some(ChatCommand::Queue),
+        "!song" => Some(ChatCommand::Song),
+        "!wrongsong" => Some(ChatCommand::WrongSong),
+        _ => None,
+    }
+}
+
+fn truncate(text: &str, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text.to_string();
+    }
+
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &text[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privmsgs_give_the_sender_and_text() {
+        let message = parse_privmsg(":ann!ann@ann.tmi.twitch.tv PRIVMSG #channel :!sr never gonna give you up ").unwrap();
+        assert_eq!(message.user, "ann");
+        assert_eq!(message.text, "!sr never gonna give you up");
+
+        let tagged = "@badge-info=;color=#FF0000;display-name=Bob :bob!bob@bob.tmi.twitch.tv PRIVMSG #channel :!queue";
+        let message = parse_privmsg(tagged).unwrap();
+        assert_eq!((message.user.as_str(), message.text.as_str()), ("bob", "!queue"));
+    }
+
+    #[test]
+    fn other_irc_lines_are_ignored() {
+        assert!(parse_privmsg("PING :tmi.twitch.tv").is_none());
+        assert!(parse_privmsg(":tmi.twitch.tv 001 justinfan12345 :Welcome, GLHF!").is_none());
+        assert!(parse_privmsg(":ann!ann@ann.tmi.twitch.tv JOIN #channel").is_none());
+        assert!(parse_privmsg("").is_none());
+    }
+
+    #[test]
+    fn commands_are_recognised() {
+        assert!(matches!(parse_command("!sr  some song "), Some(ChatCommand::SongRequest(query)) if query == "some song"));
+        assert!(matches!(parse_command("!SR https://youtu.be/abc"), Some(ChatCommand::SongRequest(_))));
+        assert!(matches!(parse_command("!queue"), Some(ChatCommand::Queue)));
+        assert!(matches!(parse_command("!song"), Some(ChatCommand::Song)));
+        assert!(matches!(parse_command("!wrongsong"), Some(ChatCommand::WrongSong)));
+        // A request needs something to look for
+        assert!(parse_command("!sr").is_none());
+        assert!(parse_command("!sr   ").is_none());
+        assert!(parse_command("hello !sr song").is_none());
+        assert!(parse_command("!songs").is_none());
+    }
+
+    #[test]
+    fn replies_are_cut_on_a_char_boundary() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("héllo world", 2), "h...");
+    }
+
+    #[tokio::test]
+    async fn titles_cannot_end_the_line() {
+        let mut written = Vec::new();
+        let title = "Song\r\nPRIVMSG #channel :spam\0";
+        send_line(&mut written, &format!("PRIVMSG #channel :@ann Added to the queue: {}", title)).await.unwrap();
+
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(written, "PRIVMSG #channel :@ann Added to the queue: Song  PRIVMSG #channel :spam \r\n");
+        assert_eq!(written.matches("\r\n").count(), 1);
+    }
+}
@@ -1,13 +1,22 @@
 use reqwest::Client;
 use serde::Deserialize;
 use anyhow::Result;
+use async_trait::async_trait;
+use crate::cache::SearchCache;
 use crate::models::*;
-use std::collections::HashSet;
+use crate::provider::{MusicProvider, SEARCH_CANDIDATES};
+use crate::quota::{ApiCall, QuotaReserved, QuotaTracker};
+use crate::limits::SongLength;
+use crate::recommend::{RecommendationContext, Seed};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone)]
 pub struct YouTubeAPI {
     client: Client,
     api_key: String,
+    song_length: SongLength,
+    cache: SearchCache,
+    quota: QuotaTracker,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,8 +40,49 @@ struct YouTubeVideoId {
 struct YouTubeSnippet {
     title: String,
     description: String,
+    #[serde(rename = "channelId")]
+    channel_id: String,
     #[serde(rename = "channelTitle")]
     channel_title: String,
+    #[serde(default)]
+    thumbnails: YouTubeThumbnails,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct YouTubeThumbnails {
+    default: Option<YouTubeThumbnail>,
+    medium: Option<YouTubeThumbnail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YouTubeThumbnail {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct YouTubeVideosResponse {
+    items: Vec<YouTubeVideoItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YouTubeVideoItem {
+    id: String,
+    snippet: YouTubeVideoSnippet,
+    #[serde(rename = "contentDetails")]
+    content_details: YouTubeContentDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct YouTubeVideoSnippet {
+    title: String,
+    #[serde(rename = "channelId")]
+    channel_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct YouTubeContentDetails {
+    // ISO-8601, e.g. PT4M13S
+    duration: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,197 +108,233 @@ struct YouTubePlaylistSnippet {
 struct YouTubeResourceId {
     #[serde(rename = "videoId")]
     video_id: String,
-    #[serde(rename = "kind")]
-    kind: Option<String>,
 }
 
 impl YouTubeAPI {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, song_length: SongLength, cache: SearchCache, quota: QuotaTracker) -> Self {
         Self {
             client: Client::new(),
             api_key,
+            song_length,
+            cache,
+            quota,
+        }
+    }
+
+    /// Sends a request to the API, counting it against the quota. The key goes in a header rather
+    /// than the query, so that request URLs in errors and logs never carry it.
+    async fn send<T: serde::Serialize + ?Sized>(&self, call: ApiCall, url: &str, params: &T) -> Result<reqwest::Response> {
+        self.quota.record(call).await;
+        let response = self
+            .client
+            .get(url)
+            .query(params)
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await;
+        #[cfg(feature = "metrics")]
+        if !response.as_ref().is_ok_and(|response| response.status().is_success()) {
+            crate::metrics::youtube_failure(call);
+        }
+        Ok(response?)
+    }
+
+    /// Looks up video details, 50 videos per request. Unknown or private videos are left out.
+    async fn fetch_videos(&self, video_ids: &[String]) -> Result<HashMap<String, VideoDetails>> {
+        let url = "https://www.googleapis.com/youtube/v3/videos";
+        let mut videos = HashMap::new();
+
+        for chunk in video_ids.chunks(50) {
+            let ids = chunk.join(",");
+            let params = [
+                ("part", "snippet,contentDetails"),
+                ("id", ids.as_str()),
+            ];
+
+            let response = self.send(ApiCall::Videos, url, &params).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let error_text = response.text().await.unwrap_or_default();
+                tracing::warn!(status = status.as_u16(), body = %error_text, "YouTube videos API error");
+                anyhow::bail!("YouTube videos request failed with status: {}", status);
+            }
+            let videos_response: YouTubeVideosResponse = response.json().await?;
+
+            for item in videos_response.items {
+                if let Some(seconds) = parse_duration(&item.content_details.duration) {
+                    let details = VideoDetails {
+                        title: item.snippet.title,
+                        channel_id: Some(item.snippet.channel_id),
+                        duration_seconds: seconds,
+                    };
+                    videos.insert(item.id, details);
+                }
+            }
         }
+
+        Ok(videos)
     }
+}
+
+#[async_trait]
+impl MusicProvider for YouTubeAPI {
+    async fn search_songs(&self, query: &str) -> Result<Vec<SearchCandidate>> {
+        // A search costs 100 quota units, chat tends to request the same songs over and over
+        let cache_key = SearchCache::search_key(query);
+        if let Some(cached) = self.cache.get(CacheKind::Search, &cache_key).await {
+            if let Ok(candidates) = serde_json::from_str(&cached) {
+                return Ok(candidates);
+            }
+        }
 
-    pub async fn search_song(&self, query: &str) -> Result<YouTubeSearchResult> {
         let url = "https://www.googleapis.com/youtube/v3/search";
+        let max_results = SEARCH_CANDIDATES.to_string();
         let params = [
             ("part", "snippet"),
             ("q", query),
             ("type", "video"),
-            ("maxResults", "1"),
-            ("key", &self.api_key),
+            ("maxResults", max_results.as_str()),
         ];
 
-        let response = self.client.get(url).query(&params).send().await?;
+        let response = self.send(ApiCall::Search, url, &params).await?;
         let status = response.status();
         if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            eprintln!("YouTube API error: Status {}, Body: {}", status, error_text);
+            tracing::warn!(status = status.as_u16(), body = %error_text, "YouTube search API error");
             anyhow::bail!("YouTube API request failed with status: {}", status);
         }
         let search_response: YouTubeSearchResponse = response.json().await?;
 
-        if let Some(item) = search_response.items.first() {
-            Ok(YouTubeSearchResult {
+        let video_ids: Vec<String> = search_response.items.iter().map(|item| item.id.video_id.clone()).collect();
+        let videos = self.fetch_videos(&video_ids).await?;
+        let candidates: Vec<SearchCandidate> = search_response
+            .items
+            .iter()
+            .map(|item| SearchCandidate {
+                video_id: item.id.video_id.clone(),
                 title: item.snippet.title.clone(),
                 url: format!("https://www.youtube.com/watch?v={}", item.id.video_id),
-                video_id: item.id.video_id.clone(),
+                channel_id: Some(item.snippet.channel_id.clone()),
+                channel_title: Some(item.snippet.channel_title.clone()),
+                thumbnail_url: item
+                    .snippet
+                    .thumbnails
+                    .medium
+                    .as_ref()
+                    .or(item.snippet.thumbnails.default.as_ref())
+                    .map(|thumbnail| thumbnail.url.clone()),
+                duration_seconds: videos.get(&item.id.video_id).map(|video| video.duration_seconds),
             })
-        } else {
-            anyhow::bail!("No matching videos found");
+            .collect();
+
+        if !candidates.is_empty() {
+            self.cache.put(CacheKind::Search, &cache_key, &serde_json::to_string(&candidates)?).await;
         }
+        Ok(candidates)
     }
 
-    pub async fn get_recommendation(&self, recent_recommendations: &[String]) -> Result<YouTubeSearchResult> {
-        let excluded_keywords = [
-            "compilation", "playlist", "mix", "mashup", "megamix",
-            "collection", "best of", "top 10", "top 20", "medley",
-            "hits of", "greatest hits", "hour", "complete album",
-            "songs", "tracks", "compilation", "non stop", "nonstop",
-            "back to back", "b2b", "music collection", "jukeboxes", "jukebox",
-            "all songs", "audio songs", "video songs", "chart", "songs",
-            "full album", "album", "live concert", "concert", "live performance",
-            "extended", "remix", "version", "cover", "covers", "acoustic",
-            "karaoke", "instrumental", "piano", "guitar", "drum", "bass",
-            "long", "extended", "full", "complete", "entire", "whole",
-            "shorts", "short", "vertical", "tiktok", "reels", "ig", "instagram",
-            "youtube shorts", "yt shorts", "short video", "short clip", "quick", "brief", 
-            "mini", "tiny", "small", "quick song", "30 second", "1 minute", "2 minute", 
-            "3 minute", "under 1 min", "under 2 min", "under 3 min"
-        ];
+    async fn get_videos(&self, video_ids: &[String]) -> Result<HashMap<String, VideoDetails>> {
+        self.fetch_videos(video_ids).await
+    }
 
-        let excluded_content_keywords = [
-            "indian", "hindi", "bollywood", "tamil", "telugu", "punjabi",
-            "bhangra", "desi", "carnatic", "bharatanatyam",
-            "chinese", "mandarin", "cantonese", "japanese", "korean", "thai",
-            "vietnamese", "arabic", "spanish", "french", "german", "italian",
-            "portuguese", "russian", "dutch", "swedish", "norwegian", "danish"
-        ];
+    async fn get_recommendation(&self, context: &RecommendationContext) -> Result<YouTubeSearchResult> {
+        if !self.quota.above_reserve().await {
+            return Err(QuotaReserved.into());
+        }
 
-        // Diverse search queries for better variety (Indonesian and English focus)
-        let search_queries = [
-            "popular music",
-            "trending songs",
-            "hit music",
-            "new music",
-            "music video",
-            "song",
-            "music",
-            "artist",
-            "band",
-            "singer",
-            "lagu indonesia",
-            "musik indonesia",
-            "indonesian music",
-            "lagu pop indonesia",
-            "indonesian pop"
-        ];
-        
-        // Randomly select a query for variety
+        let settings = &context.settings;
         use rand::seq::SliceRandom;
-        let query = search_queries.choose(&mut rand::thread_rng()).unwrap();
-
         let url = "https://www.googleapis.com/youtube/v3/search";
         
-        // Randomly vary search parameters for more diversity
-        let order_options = ["relevance", "date", "rating", "viewCount"];
-        let order = order_options.choose(&mut rand::thread_rng()).unwrap();
-        
-        let params = [
+        let mut params = vec![
             ("part", "snippet"),
-            ("q", query),
             ("type", "video"),
-            ("videoCategoryId", "10"), // Music category
             ("maxResults", "50"), // Increased for more variety
-            ("key", &self.api_key),
-            ("relevanceLanguage", "id"), // Indonesian language preference
-            ("order", order),
         ];
+        if !settings.video_category_id.is_empty() {
+            params.push(("videoCategoryId", settings.video_category_id.as_str()));
+        }
+        if !settings.relevance_language.is_empty() {
+            params.push(("relevanceLanguage", settings.relevance_language.as_str()));
+        }
+        if !settings.region_code.is_empty() {
+            params.push(("regionCode", settings.region_code.as_str()));
+        }
 
-        let response = self.client.get(url).query(&params).send().await?;
+        // Search around something the room has requested, falling back to a generic query
+        let seed = context.seeds.pick();
+        match &seed {
+            Some(Seed::Artist(artist)) => params.extend([("q", artist.as_str()), ("order", "relevance")]),
+            Some(Seed::Song(title)) => params.extend([("q", title.as_str()), ("order", "relevance")]),
+            Some(Seed::Channel(channel_id)) => params.extend([("channelId", channel_id.as_str()), ("order", "viewCount")]),
+            None => {
+                // Randomly vary search parameters for more diversity
+                let order_options = ["relevance", "date", "rating", "viewCount"];
+                let query = settings.search_queries.choose(&mut rand::thread_rng()).map_or("music", String::as_str);
+                params.push(("q", query));
+                params.push(("order", order_options.choose(&mut rand::thread_rng()).unwrap()));
+            }
+        }
+
+        let response = self.send(ApiCall::Search, url, &params).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            tracing::warn!(status = status.as_u16(), body = %error_text, "YouTube recommendation search API error");
+            anyhow::bail!("YouTube recommendation search failed with status: {}", status);
+        }
         let search_response: YouTubeSearchResponse = response.json().await?;
 
-        let recent_set: HashSet<String> = recent_recommendations.iter().cloned().collect();
+        let recent_set: HashSet<String> = context.excluded.iter().cloned().collect();
+
+        // Shorts, hour-long loops and mixes are ruled out by their real length
+        let video_ids: Vec<String> = search_response.items.iter().map(|item| item.id.video_id.clone()).collect();
+        let videos = self.fetch_videos(&video_ids).await?;
+        let fits_length = |item: &&YouTubeSearchItem| {
+            videos
+                .get(&item.id.video_id)
+                .is_some_and(|video| self.song_length.allows(video.duration_seconds))
+        };
+        // Blocked songs are never recommended, not even as a fallback
+        let items: Vec<&YouTubeSearchItem> = search_response
+            .items
+            .iter()
+            .filter(|item| {
+                context
+                    .blocklist
+                    .check_song(&item.id.video_id, Some(&item.snippet.channel_id), &item.snippet.title)
+                    .is_ok()
+            })
+            .collect();
 
         // Filter out compilations and recently recommended videos
-        let filtered_items: Vec<&YouTubeSearchItem> = search_response
-            .items
+        let filtered_items: Vec<&YouTubeSearchItem> = items
             .iter()
+            .copied()
             .filter(|item| {
-                let title_lower = item.snippet.title.to_lowercase();
-                let desc_lower = item.snippet.description.to_lowercase();
-                let channel_lower = item.snippet.channel_title.to_lowercase();
-
-                // Check for compilation keywords
-                let is_compilation = excluded_keywords.iter().any(|keyword| {
-                    title_lower.contains(keyword) || desc_lower.contains(keyword)
-                });
-
-                // Check for excluded content
-                let is_excluded_content = excluded_content_keywords.iter().any(|keyword| {
-                    title_lower.contains(keyword) || 
-                    channel_lower.contains(keyword) || 
-                    desc_lower.contains(keyword)
-                });
-
-                // Check for duration indicators (more strict)
-                let has_duration = title_lower.contains("minute") || 
-                                 title_lower.contains("hour") ||
-                                 title_lower.contains("min") ||
-                                 title_lower.contains("hr") ||
-                                 regex::Regex::new(r"\d+\s*(min|minute|hr|hour)").unwrap().is_match(&title_lower);
-
-                // Check for YouTube Shorts specifically
-                let is_youtube_shorts = title_lower.contains("#shorts") || 
-                                       title_lower.contains("youtube shorts") ||
-                                       title_lower.contains("yt shorts") ||
-                                       title_lower.contains("short video") ||
-                                       title_lower.contains("short clip") ||
-                                       desc_lower.contains("#shorts") ||
-                                       desc_lower.contains("youtube shorts") ||
-                                       channel_lower.contains("shorts") ||
-                                       regex::Regex::new(r"#shorts").unwrap().is_match(&title_lower) ||
-                                       regex::Regex::new(r"#shorts").unwrap().is_match(&desc_lower) ||
-                                       regex::Regex::new(r"\d+\s*(second|sec)").unwrap().is_match(&title_lower) ||
-                                       regex::Regex::new(r"under\s+\d+\s*(min|minute)").unwrap().is_match(&title_lower);
-
-                // Check for any hashtag symbols (exclude all videos with #)
-                let has_hashtag = title_lower.contains("#") || desc_lower.contains("#");
+                // Compilations, hashtags, long titles and content the room does not want, see [recommendations] in the config
+                let is_excluded = settings.excludes(&item.snippet.title, &item.snippet.description, &item.snippet.channel_title);
 
                 // Check if recently recommended
                 let is_recently_recommended = recent_set.contains(&item.id.video_id);
 
-                // Check title length (very long titles often indicate compilations)
-                let is_too_long = title_lower.len() > 70 && title_lower.split_whitespace().count() > 10;
-
-                !is_compilation && !is_excluded_content && !has_duration && !is_youtube_shorts && !has_hashtag && !is_recently_recommended && !is_too_long
+                !is_excluded && !is_recently_recommended
             })
+            .filter(fits_length)
             .collect();
 
         if filtered_items.is_empty() {
             // If no filtered results, use all items but avoid recent ones
-            let available_items: Vec<&YouTubeSearchItem> = search_response
-                .items
+            let available_items: Vec<&YouTubeSearchItem> = items
                 .iter()
+                .copied()
                 .filter(|item| !recent_set.contains(&item.id.video_id))
+                .filter(fits_length)
                 .collect();
 
-            if let Some(item) = available_items.first() {
-                Ok(YouTubeSearchResult {
-                    title: item.snippet.title.clone(),
-                    url: format!("https://www.youtube.com/watch?v={}", item.id.video_id),
-                    video_id: item.id.video_id.clone(),
-                })
-            } else {
-                // If all items are recently recommended, pick the first one
-                let item = &search_response.items[0];
-                Ok(YouTubeSearchResult {
-                    title: item.snippet.title.clone(),
-                    url: format!("https://www.youtube.com/watch?v={}", item.id.video_id),
-                    video_id: item.id.video_id.clone(),
-                })
-            }
+            // Replaying a recent song or one outside the length limits is worse than no recommendation
+            let item = available_items.first().ok_or_else(|| anyhow::anyhow!("No recommendable videos found"))?;
+            Ok(search_result(item, &videos))
         } else {
             // Pick a truly random item from filtered results
             use rand::seq::SliceRandom;
@@ -259,19 +345,15 @@ impl YouTubeAPI {
             
             let item = shuffled_items.first().unwrap();
             
-            Ok(YouTubeSearchResult {
-                title: item.snippet.title.clone(),
-                url: format!("https://www.youtube.com/watch?v={}", item.id.video_id),
-                video_id: item.id.video_id.clone(),
-            })
+            Ok(search_result(item, &videos))
         }
     }
 
-    pub async fn get_playlist_songs(&self, playlist_url: &str, played_songs: &[String]) -> Result<Vec<YouTubeSearchResult>> {
+    async fn get_playlist_songs(&self, playlist_url: &str, played_songs: &[String]) -> Result<Vec<YouTubeSearchResult>> {
         let playlist_id = Self::extract_playlist_id(playlist_url)
             .ok_or_else(|| anyhow::anyhow!("Invalid playlist URL"))?;
         
-        eprintln!("Extracted playlist ID: {}", playlist_id);
+        tracing::debug!(playlist_id = %playlist_id, "Fetching YouTube playlist");
         
         let mut all_songs = Vec::new();
         let mut next_page_token: Option<String> = None;
@@ -283,50 +365,81 @@ impl YouTubeAPI {
                 ("part", "snippet"),
                 ("playlistId", &playlist_id),
                 ("maxResults", "50"),
-                ("key", &self.api_key),
             ];
             
             if let Some(ref token) = next_page_token {
                 params.push(("pageToken", token));
             }
 
-            let response = self.client.get(url).query(&params).send().await?;
-            let status = response.status();
-            if !status.is_success() {
-                let error_text = response.text().await.unwrap_or_default();
-                eprintln!("YouTube Playlist API error: Status {}, Body: {}", status, error_text);
-                eprintln!("Request URL: {}", url);
-                eprintln!("Request params: {:?}", params);
-                anyhow::bail!("YouTube Playlist API request failed with status: {}", status);
-            }
-            
-            let response_text = response.text().await?;
-            eprintln!("YouTube API Response (page {}): {} songs", total_fetched / 50 + 1, response_text.len());
+            let page_key = SearchCache::playlist_page_key(&playlist_id, next_page_token.as_deref());
+            let cached = self.cache.get(CacheKind::PlaylistPage, &page_key).await;
+            let from_cache = cached.is_some();
+            let response_text = match cached {
+                Some(cached) => cached,
+                None => {
+                    let response = self.send(ApiCall::PlaylistItems, url, &params).await?;
+                    let status = response.status();
+                    if !status.is_success() {
+                        let error_text = response.text().await.unwrap_or_default();
+                        tracing::warn!(
+                            status = status.as_u16(),
+                            body = %error_text,
+                            playlist_id = %playlist_id,
+                            page_token = next_page_token.as_deref(),
+                            "YouTube playlist API error"
+                        );
+                        anyhow::bail!("YouTube Playlist API request failed with status: {}", status);
+                    }
+                    response.text().await?
+                }
+            };
+            tracing::debug!(
+                page = total_fetched / 50 + 1,
+                cached = from_cache,
+                bytes = response_text.len(),
+                "YouTube playlist page received"
+            );
             
             // Parse as generic JSON first to see the structure
             let json_value: serde_json::Value = serde_json::from_str(&response_text)
                 .map_err(|e| {
-                    eprintln!("JSON parsing error: {}", e);
+                    tracing::warn!(error = %e, "YouTube playlist page is not JSON");
                     e
                 })?;
             
             let playlist_response: YouTubePlaylistResponse = serde_json::from_value(json_value)
                 .map_err(|e| {
-                    eprintln!("Struct parsing error: {}", e);
+                    tracing::warn!(error = %e, "Unexpected YouTube playlist page");
                     e
                 })?;
+            if !from_cache {
+                self.cache.put(CacheKind::PlaylistPage, &page_key, &response_text).await;
+            }
             
             let played_set: std::collections::HashSet<String> = played_songs.iter().cloned().collect();
             
             let items_count = playlist_response.items.len();
+            let page_ids: Vec<String> = playlist_response
+                .items
+                .iter()
+                .map(|item| item.snippet.resource_id.video_id.clone())
+                .filter(|video_id| !played_set.contains(video_id))
+                .collect();
+            let videos = self.fetch_videos(&page_ids).await?;
+
             let page_songs: Vec<YouTubeSearchResult> = playlist_response
                 .items
                 .into_iter()
                 .filter(|item| !played_set.contains(&item.snippet.resource_id.video_id))
-                .map(|item| YouTubeSearchResult {
-                    title: item.snippet.title,
-                    url: format!("https://www.youtube.com/watch?v={}", item.snippet.resource_id.video_id),
-                    video_id: item.snippet.resource_id.video_id,
+                .map(|item| {
+                    let video = videos.get(&item.snippet.resource_id.video_id);
+                    YouTubeSearchResult {
+                        title: item.snippet.title,
+                        url: format!("https://www.youtube.com/watch?v={}", item.snippet.resource_id.video_id),
+                        duration_seconds: video.map(|video| video.duration_seconds),
+                        channel_id: video.and_then(|video| video.channel_id.clone()),
+                        video_id: item.snippet.resource_id.video_id,
+                    }
                 })
                 .collect();
             
@@ -336,23 +449,25 @@ impl YouTubeAPI {
             // Check if there are more pages
             if let Some(next_token) = playlist_response.next_page_token {
                 next_page_token = Some(next_token);
-                eprintln!("Fetched {} songs so far, continuing to next page...", total_fetched);
+                tracing::debug!(fetched = total_fetched, "Fetching the next playlist page");
             } else {
-                eprintln!("No more pages, total songs fetched: {}", total_fetched);
+                tracing::debug!(fetched = total_fetched, "Fetched the last playlist page");
                 break;
             }
             
             // Safety limit to prevent infinite loops
             if total_fetched > 1000 {
-                eprintln!("Reached safety limit of 1000 songs, stopping");
+                tracing::warn!(playlist_id = %playlist_id, "Reached safety limit of 1000 songs, stopping");
                 break;
             }
         }
 
-        eprintln!("Total songs fetched: {}", all_songs.len());
+        tracing::info!(playlist_id = %playlist_id, songs = all_songs.len(), "Fetched YouTube playlist");
         Ok(all_songs)
     }
+}
 
+impl YouTubeAPI {
     pub fn extract_playlist_id(url: &str) -> Option<String> {
         if url.contains("list=") {
             if let Some(parts) = url.split("list=").nth(1) {
@@ -375,3 +490,81 @@ impl YouTubeAPI {
         None
     }
 }
+
+fn search_result(item: &YouTubeSearchItem, videos: &HashMap<String, VideoDetails>) -> YouTubeSearchResult {
+    YouTubeSearchResult {
+        title: item.snippet.title.clone(),
+        url: format!("https://www.youtube.com/watch?v={}", item.id.video_id),
+        video_id: item.id.video_id.clone(),
+        duration_seconds: videos.get(&item.id.video_id).map(|video| video.duration_seconds),
+        channel_id: Some(item.snippet.channel_id.clone()),
+    }
+}
+
+/// Parses an ISO-8601 duration such as `PT1H2M3S` or `P1DT2H` into seconds.
+fn parse_duration(iso: &str) -> Option<i64> {
+    let rest = iso.strip_prefix('P')?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in rest.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' => number.push(c),
+            unit => {
+                let value: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += value
+                    * match (unit, in_time) {
+                        ('W', false) => 7 * 86400,
+                        ('D', false) => 86400,
+                        ('H', true) => 3600,
+                        ('M', true) => 60,
+                        ('S', true) => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+
+    number.is_empty().then_some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_read_from_iso_8601() {
+        assert_eq!(parse_duration("PT3M33S"), Some(213));
+        assert_eq!(parse_duration("PT1H2M3S"), Some(3723));
+        assert_eq!(parse_duration("PT45S"), Some(45));
+        assert_eq!(parse_duration("PT10H"), Some(36000));
+        assert_eq!(parse_duration("P1DT2H"), Some(93600));
+        assert_eq!(parse_duration("P1W"), Some(604800));
+        // Live streams report no length
+        assert_eq!(parse_duration("P0D"), Some(0));
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("3M33S"), None);
+        assert_eq!(parse_duration("PT3X"), None);
+        // Minutes before the T would be months
+        assert_eq!(parse_duration("P3M"), None);
+        assert_eq!(parse_duration("PT3M33"), None);
+        assert_eq!(parse_duration("PTM"), None);
+    }
+
+    #[test]
+    fn video_ids_are_taken_from_both_url_forms() {
+        assert_eq!(
+            YouTubeAPI::extract_video_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42").as_deref(),
+            Some("dQw4w9WgXcQ")
+        );
+        assert_eq!(YouTubeAPI::extract_video_id("https://youtu.be/dQw4w9WgXcQ?t=42").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(YouTubeAPI::extract_video_id("https://example.com/video"), None);
+    }
+}